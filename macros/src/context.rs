@@ -0,0 +1,208 @@
+//! Expansion of `wenzetu::context!`
+//!
+//! Entries are parsed here instead of by a recursive `macro_rules!`, which
+//! hit the recursion limit on large contexts.
+
+use proc_macro2::{Span, TokenStream, TokenTree};
+use quote::quote;
+use syn::ext::IdentExt;
+use syn::parse::{Parse, ParseStream};
+use syn::{Expr, Ident, Lit, Token, braced};
+
+/// `$crate; entries`, as passed by the `context!` wrapper
+pub(crate) struct ContextInput {
+    krate: TokenTree,
+    entries: Vec<Entry>,
+}
+
+enum Entry {
+    /// `..value`
+    Spread(Expr),
+    /// `if cond => { entries }`
+    IfBlock(Expr, Vec<Entry>),
+    /// `if cond => key: value`
+    IfPair(Expr, Pair),
+    /// `key: value`
+    Pair(Pair),
+}
+
+struct Pair {
+    key: TokenStream,
+    value: Value,
+}
+
+enum Value {
+    /// `#{ entries }`
+    Map(Vec<Entry>),
+    Expr(Expr),
+}
+
+impl Parse for ContextInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let krate = input.parse()?;
+        input.parse::<Token![;]>()?;
+        Ok(Self {
+            krate,
+            entries: parse_entries(input)?,
+        })
+    }
+}
+
+fn parse_entries(input: ParseStream) -> syn::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    while !input.is_empty() {
+        entries.push(input.parse()?);
+        if input.is_empty() {
+            break;
+        }
+        input.parse::<Token![,]>()?;
+    }
+    Ok(entries)
+}
+
+impl Parse for Entry {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.peek(Token![..]) {
+            input.parse::<Token![..]>()?;
+            return Ok(Entry::Spread(input.parse()?));
+        }
+        if input.peek(Token![if]) {
+            input.parse::<Token![if]>()?;
+            let cond = Expr::parse_without_eager_brace(input)?;
+            input.parse::<Token![=>]>()?;
+            if input.peek(syn::token::Brace) {
+                let content;
+                braced!(content in input);
+                return Ok(Entry::IfBlock(cond, parse_entries(&content)?));
+            }
+            return Ok(Entry::IfPair(cond, input.parse()?));
+        }
+        Ok(Entry::Pair(input.parse()?))
+    }
+}
+
+impl Parse for Pair {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let key = if input.peek(Lit) {
+            let key: Lit = input.parse()?;
+            quote!(#key)
+        } else {
+            // Any identifier, keywords included, named as written
+            let key = Ident::parse_any(input)?;
+            let name = key.to_string();
+            quote!(#name)
+        };
+        input.parse::<Token![:]>()?;
+
+        let value = if input.peek(Token![#]) && input.peek2(syn::token::Brace) {
+            input.parse::<Token![#]>()?;
+            let content;
+            braced!(content in input);
+            Value::Map(parse_entries(&content)?)
+        } else {
+            Value::Expr(input.parse()?)
+        };
+        Ok(Pair { key, value })
+    }
+}
+
+/// Build the context as a block expression
+pub(crate) fn expand(input: ContextInput) -> TokenStream {
+    // Not visible to the values, like a `macro_rules!` local
+    let ctx = Ident::new("ctx", Span::mixed_site());
+    context(&input.krate, &ctx, &input.entries)
+}
+
+fn context(krate: &TokenTree, ctx: &Ident, entries: &[Entry]) -> TokenStream {
+    let inserts = entries.iter().map(|entry| insert(krate, ctx, entry));
+    quote! {{
+        #[allow(unused_mut)]
+        let mut #ctx = #krate::tera::Context::new();
+        #(#inserts)*
+        #ctx
+    }}
+}
+
+fn insert(krate: &TokenTree, ctx: &Ident, entry: &Entry) -> TokenStream {
+    match entry {
+        Entry::Spread(value) => quote! {
+            #ctx.extend(
+                #krate::tera::Context::from_serialize(&#value)
+                    .expect("context! can only spread values that serialize to a map"),
+            );
+        },
+        Entry::IfBlock(cond, entries) => {
+            let inserts = entries.iter().map(|entry| insert(krate, ctx, entry));
+            quote! {
+                if #cond {
+                    #(#inserts)*
+                }
+            }
+        }
+        Entry::IfPair(cond, pair) => {
+            let insert = insert_pair(krate, ctx, pair);
+            quote! {
+                if #cond {
+                    #insert
+                }
+            }
+        }
+        Entry::Pair(pair) => insert_pair(krate, ctx, pair),
+    }
+}
+
+fn insert_pair(krate: &TokenTree, ctx: &Ident, pair: &Pair) -> TokenStream {
+    let key = &pair.key;
+    match &pair.value {
+        Value::Map(entries) => {
+            let map = context(krate, ctx, entries);
+            quote!(#ctx.insert(#key, &#map.into_json());)
+        }
+        Value::Expr(value) => quote!(#ctx.insert(#key, &#value);),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_str(entries: &str) -> syn::Result<String> {
+        let input = syn::parse_str::<ContextInput>(&format!("krate; {}", entries))?;
+        Ok(expand(input).to_string())
+    }
+
+    #[test]
+    fn expands_large_contexts_without_recursion() {
+        let entries: Vec<String> = (0..500).map(|i| format!("key_{}: {}", i, i)).collect();
+        let output = expand_str(&entries.join(", ")).unwrap();
+        assert_eq!(output.matches(". insert (").count(), 500);
+        assert!(
+            output.contains(r#"insert ("key_499" , & 499)"#),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn keeps_blocks_as_expressions_and_nests_maps() {
+        let output =
+            expand_str(r#"total: { 1 + 2 }, user: #{ id: 7 }, "page-title": "Home","#).unwrap();
+        assert!(
+            output.contains(r#"insert ("total" , & { 1 + 2 })"#),
+            "{}",
+            output
+        );
+        assert!(output.contains(". into_json ()"), "{}", output);
+        assert!(
+            output.contains(r#"insert ("page-title" , & "Home")"#),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn reports_malformed_entries() {
+        assert!(expand_str("title \"Home\"").is_err());
+        assert!(expand_str("if ready => ").is_err());
+    }
+}
@@ -0,0 +1,344 @@
+//! Derive macros for wenzetu
+//!
+//! These are re-exported by `wenzetu`; depend on that crate instead.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{Data, DeriveInput, Fields, LitStr, parse_macro_input};
+
+mod context;
+
+/// Bind a struct to a template, see `wenzetu::typed::WenzetuTemplate`.
+#[proc_macro_derive(WenzetuTemplate, attributes(template))]
+pub fn derive_wenzetu_template(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+/// Expand `wenzetu::context!`, which passes `$crate` before the entries.
+#[doc(hidden)]
+#[proc_macro]
+pub fn context(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as context::ContextInput);
+    context::expand(input).into()
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let template = template_name(&input)?;
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(named) => &named.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "WenzetuTemplate can only be derived for structs with named fields",
+                ));
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "WenzetuTemplate can only be derived for structs",
+            ));
+        }
+    };
+
+    let rename_all = rename_rule(&input)?;
+    let mut names = Vec::new();
+    for field in fields {
+        if let Some(name) = serialized_name(field, rename_all)? {
+            names.push(name);
+        }
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::wenzetu::typed::WenzetuTemplate for #ident #ty_generics #where_clause {
+            const TEMPLATE: &'static str = #template;
+            const FIELDS: &'static [&'static str] = &[#(#names),*];
+        }
+    })
+}
+
+/// Read the template name from `#[template("name.html")]`
+fn template_name(input: &DeriveInput) -> syn::Result<LitStr> {
+    let attr = input
+        .attrs
+        .iter()
+        .find(|attr| attr.path().is_ident("template"))
+        .ok_or_else(|| {
+            syn::Error::new_spanned(&input.ident, "missing #[template(\"name.html\")] attribute")
+        })?;
+
+    attr.parse_args::<LitStr>()
+}
+
+/// Case convention of `#[serde(rename_all = "...")]`, applied to field
+/// names the way serde does
+#[derive(Clone, Copy)]
+enum RenameRule {
+    Lower,
+    Upper,
+    Pascal,
+    Camel,
+    Snake,
+    ScreamingSnake,
+    Kebab,
+    ScreamingKebab,
+}
+
+impl RenameRule {
+    fn parse(value: &LitStr) -> syn::Result<Self> {
+        Ok(match value.value().as_str() {
+            "lowercase" => RenameRule::Lower,
+            "UPPERCASE" => RenameRule::Upper,
+            "PascalCase" => RenameRule::Pascal,
+            "camelCase" => RenameRule::Camel,
+            "snake_case" => RenameRule::Snake,
+            "SCREAMING_SNAKE_CASE" => RenameRule::ScreamingSnake,
+            "kebab-case" => RenameRule::Kebab,
+            "SCREAMING-KEBAB-CASE" => RenameRule::ScreamingKebab,
+            other => {
+                return Err(syn::Error::new_spanned(
+                    value,
+                    format!("unknown rename rule `rename_all = \"{}\"`", other),
+                ));
+            }
+        })
+    }
+
+    /// Rename a snake_case field name
+    fn apply(self, field: &str) -> String {
+        match self {
+            RenameRule::Lower | RenameRule::Snake => field.to_string(),
+            RenameRule::Upper | RenameRule::ScreamingSnake => field.to_ascii_uppercase(),
+            RenameRule::Pascal => {
+                let mut pascal = String::new();
+                let mut capitalize = true;
+                for ch in field.chars() {
+                    if ch == '_' {
+                        capitalize = true;
+                    } else if capitalize {
+                        pascal.push(ch.to_ascii_uppercase());
+                        capitalize = false;
+                    } else {
+                        pascal.push(ch);
+                    }
+                }
+                pascal
+            }
+            RenameRule::Camel => {
+                let pascal = RenameRule::Pascal.apply(field);
+                let mut chars = pascal.chars();
+                chars.next().map_or_else(String::new, |first| {
+                    first.to_ascii_lowercase().to_string() + chars.as_str()
+                })
+            }
+            RenameRule::Kebab => field.replace('_', "-"),
+            RenameRule::ScreamingKebab => field.to_ascii_uppercase().replace('_', "-"),
+        }
+    }
+}
+
+/// Read the container's `#[serde(rename_all = "...")]`, or its
+/// `serialize` rule
+fn rename_rule(input: &DeriveInput) -> syn::Result<Option<RenameRule>> {
+    let mut rule = None;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("serde"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename_all") && meta.input.peek(syn::token::Paren) {
+                // `rename_all(serialize = "...", deserialize = "...")`
+                meta.parse_nested_meta(|inner| {
+                    let value = inner.value()?.parse::<LitStr>()?;
+                    if inner.path.is_ident("serialize") {
+                        rule = Some(RenameRule::parse(&value)?);
+                    }
+                    Ok(())
+                })?;
+            } else if meta.path.is_ident("rename_all") {
+                rule = Some(RenameRule::parse(&meta.value()?.parse::<LitStr>()?)?);
+            } else {
+                skip_meta(&meta)?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(rule)
+}
+
+/// Name a field is serialized under, honoring `#[serde(rename)]` and the
+/// container's `rename_all`, and returning `None` for `#[serde(skip)]`
+/// fields.
+///
+/// `#[serde(flatten)]` is rejected: the flattened type's fields are only
+/// known to serde at runtime, so they can't be listed here.
+fn serialized_name(
+    field: &syn::Field,
+    rename_all: Option<RenameRule>,
+) -> syn::Result<Option<String>> {
+    let mut name = field.ident.as_ref().map(|ident| {
+        let name = ident.to_string();
+        let name = name.strip_prefix("r#").map(str::to_string).unwrap_or(name);
+        match rename_all {
+            Some(rule) => rule.apply(&name),
+            None => name,
+        }
+    });
+
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("serde"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") && meta.input.peek(syn::token::Paren) {
+                // `rename(serialize = "...", deserialize = "...")`
+                meta.parse_nested_meta(|inner| {
+                    let value = inner.value()?.parse::<LitStr>()?;
+                    if inner.path.is_ident("serialize") {
+                        name = Some(value.value());
+                    }
+                    Ok(())
+                })?;
+            } else if meta.path.is_ident("rename") {
+                name = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("skip") || meta.path.is_ident("skip_serializing") {
+                name = None;
+            } else if meta.path.is_ident("flatten") {
+                return Err(meta.error(
+                    "WenzetuTemplate does not support #[serde(flatten)], \
+                     list the flattened fields on the struct instead",
+                ));
+            } else {
+                skip_meta(&meta)?;
+            }
+            Ok(())
+        })?;
+    }
+
+    Ok(name)
+}
+
+/// Skip the value of a serde option we don't need, like `default = "..."`
+fn skip_meta(meta: &syn::meta::ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(syn::Token![=]) {
+        meta.value()?.parse::<syn::Expr>()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        meta.input.parse::<proc_macro2::Group>()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(input: DeriveInput) -> String {
+        expand(input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn lists_serialized_field_names() {
+        let output = expand(syn::parse_quote! {
+            #[template("page.html")]
+            struct Page {
+                title: String,
+                #[serde(rename = "heading", default)]
+                header: String,
+                #[serde(skip)]
+                cache: String,
+            }
+        })
+        .unwrap()
+        .to_string();
+
+        assert!(output.contains(r#"const TEMPLATE : & 'static str = "page.html""#));
+        assert!(output.contains(r#"& ["title" , "heading"]"#), "{}", output);
+    }
+
+    #[test]
+    fn requires_the_template_attribute() {
+        let message = error(syn::parse_quote! {
+            struct Page { title: String }
+        });
+        assert!(message.contains("missing #[template"), "{}", message);
+    }
+
+    #[test]
+    fn rejects_tuple_structs_and_enums() {
+        let message = error(syn::parse_quote! {
+            #[template("page.html")]
+            struct Page(String);
+        });
+        assert!(message.contains("named fields"), "{}", message);
+
+        let message = error(syn::parse_quote! {
+            #[template("page.html")]
+            enum Page { Home }
+        });
+        assert!(
+            message.contains("only be derived for structs"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn applies_the_container_rename_rule() {
+        let output = expand(syn::parse_quote! {
+            #[derive(Serialize)]
+            #[serde(rename_all = "camelCase")]
+            #[template("page.html")]
+            struct Page {
+                page_title: String,
+                #[serde(rename = "by")]
+                author_name: String,
+                r#type: String,
+            }
+        })
+        .unwrap()
+        .to_string();
+        assert!(
+            output.contains(r#"& ["pageTitle" , "by" , "type"]"#),
+            "{}",
+            output
+        );
+
+        let output = expand(syn::parse_quote! {
+            #[serde(rename_all(serialize = "SCREAMING-KEBAB-CASE"))]
+            #[template("page.html")]
+            struct Page { page_title: String }
+        })
+        .unwrap()
+        .to_string();
+        assert!(output.contains(r#"& ["PAGE-TITLE"]"#), "{}", output);
+    }
+
+    #[test]
+    fn rejects_unknown_rename_rules_and_flatten() {
+        let message = error(syn::parse_quote! {
+            #[serde(rename_all = "Title Case")]
+            #[template("page.html")]
+            struct Page { title: String }
+        });
+        assert!(message.contains("unknown rename rule"), "{}", message);
+
+        let message = error(syn::parse_quote! {
+            #[template("page.html")]
+            struct Page {
+                #[serde(flatten)]
+                meta: Meta,
+            }
+        });
+        assert!(message.contains("flatten"), "{}", message);
+    }
+}
@@ -1,22 +1,65 @@
 //! Application builder for simplified setup
 
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::time::Duration;
+
+use axum::Router;
+use http::Extensions;
 use uncovr::{
     config::AppConfig as UncovRConfig, config::Environment, prelude::ApiRouter, server::Server,
 };
 
-use crate::config::{load_config, to_uncovr_config};
+use crate::assets::{self, AssetManifest};
+use crate::config::{AppConfig, apply_uncovr_config, to_uncovr_config, try_load_config};
+use crate::engine::Engine;
+use crate::error::{BoxError, Error};
+use crate::error_pages::{self, ErrorPagesLayer};
+#[cfg(feature = "live-reload")]
+use crate::live_reload;
+use crate::shutdown::{self, ShutdownHook};
+use crate::state::StateLayer;
 use crate::static_files;
 use crate::templates;
 
+/// Environment variable naming the file [`App::serve`] writes its bound
+/// address to, set by the development runner
+pub(crate) const DEV_ADDR_FILE: &str = "WENZETU_DEV_ADDR_FILE";
+
 /// Builder for creating web applications with sensible defaults
 pub struct App {
     config: Option<UncovRConfig>,
+    /// Settings loaded by `auto_config`
+    settings: Option<AppConfig>,
+    /// Why `auto_config` couldn't load the settings, returned by `serve`
+    config_error: Option<Error>,
     web_routes: Option<ApiRouter>,
     api_routes: Option<ApiRouteConfig>,
-    static_config: Option<StaticConfig>,
-    enable_live_reload: bool,
+    extra_routes: Vec<ApiRouter>,
+    /// The default `/public` mount or the one from `auto_config`, replaced
+    /// by the first explicit static mount
+    default_static: Option<StaticConfig>,
+    static_configs: Vec<StaticConfig>,
+    /// Error templates by status code
+    error_templates: Vec<(u16, String)>,
+    enable_live_reload: Option<bool>,
+    live_reload_ignore: Vec<String>,
+    /// Watcher started by `serve`, shared with [`App::watcher`]
+    #[cfg(feature = "live-reload")]
+    watcher: live_reload::AppWatcher,
+    /// How long to wait for open requests on shutdown, `None` runs until
+    /// the process is killed
+    shutdown_timeout: Option<Duration>,
+    shutdown_hooks: Vec<ShutdownHook>,
+    startup_hooks: Vec<StartupHook>,
+    ready_hooks: Vec<ReadyHook>,
+    /// Values added with `state`, by type
+    state: Extensions,
     environment: Option<Environment>,
     templates_path: Option<String>,
+    template_engine: Option<Engine>,
     docs_path: Option<String>,
     openapi_json_path: Option<String>,
 }
@@ -33,6 +76,281 @@ pub struct ApiRouteConfig {
 pub struct StaticConfig {
     pub serve_path: String,
     pub directory: String,
+    /// Serve `.br`/`.gz` siblings of a file when the client accepts them
+    /// and they are not older than the file
+    pub precompressed: bool,
+    /// Compress files when first requested, keeping the results in
+    /// `compress_cache_dir` (requires the `compression` feature)
+    pub compress: bool,
+    /// Directory compressed files are kept in, outside the static directory
+    pub compress_cache_dir: String,
+    /// Cache-Control rules, the first rule matching a file applies
+    pub cache_rules: Vec<CacheRule>,
+    /// Cache-Control for files no rule matches
+    pub default_cache_control: String,
+    /// Send strong ETags and answer `If-None-Match` with `304 Not Modified`
+    pub etag: bool,
+    /// Send `Last-Modified` and answer `If-Modified-Since`
+    pub last_modified: bool,
+    /// Serve files under content-hashed names resolved by `asset()`
+    pub fingerprint: bool,
+    /// Manifest generated at build time, the directory is scanned at
+    /// startup when unset
+    pub manifest: Option<String>,
+    /// Serve `index.html` for unknown paths without a file extension, for
+    /// single-page apps with client-side routing
+    pub spa: bool,
+    /// Path prefixes never answered with the single-page app's `index.html`
+    pub spa_exclude: Vec<String>,
+    /// Which files may be served
+    pub options: StaticOptions,
+    /// Serve resized images under `_img/{width}x{height}/`
+    #[cfg(feature = "images")]
+    pub images: Option<crate::images::ImageOptions>,
+    /// Files compiled into the binary, served instead of `directory` outside
+    /// of Development
+    #[cfg(feature = "embedded-assets")]
+    pub embedded: Option<crate::embedded::EmbeddedFiles>,
+}
+
+/// Access rules for static files.
+///
+/// Denied files are answered with `404 Not Found`, as if they didn't exist.
+///
+/// # Example
+/// ```rust
+/// use wenzetu::app::{StaticConfig, StaticOptions};
+///
+/// let config = StaticConfig::new("/public", "./public").options(
+///     StaticOptions::default()
+///         .follow_symlinks(false)
+///         .allowed_extensions(["css", "js", "png", "html"]),
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct StaticOptions {
+    /// Serve files and directories whose name starts with `.`, such as
+    /// `.env` or `.git` (off by default)
+    pub dotfiles: bool,
+    /// Serve files reached through symlinks (on by default). Symlinks
+    /// leading outside the static directory are denied either way
+    pub follow_symlinks: bool,
+    /// List directories without an `index.html`, only in Development and
+    /// for files on disk (off by default)
+    pub autoindex: bool,
+    /// Extensions that may be served, without the dot; every extension when
+    /// empty
+    pub allowed_extensions: Vec<String>,
+}
+
+impl Default for StaticOptions {
+    fn default() -> Self {
+        Self {
+            dotfiles: false,
+            follow_symlinks: true,
+            autoindex: false,
+            allowed_extensions: Vec::new(),
+        }
+    }
+}
+
+impl StaticOptions {
+    /// Allow or deny files and directories starting with `.`
+    pub fn dotfiles(mut self, allowed: bool) -> Self {
+        self.dotfiles = allowed;
+        self
+    }
+
+    /// Follow or deny symlinks inside the static directory; symlinks leading
+    /// outside of it are always denied
+    pub fn follow_symlinks(mut self, enabled: bool) -> Self {
+        self.follow_symlinks = enabled;
+        self
+    }
+
+    /// Enable or disable directory listings in Development
+    pub fn autoindex(mut self, enabled: bool) -> Self {
+        self.autoindex = enabled;
+        self
+    }
+
+    /// Only serve files with these extensions, e.g. `["css", "js"]`
+    pub fn allowed_extensions<I, S>(mut self, extensions: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.allowed_extensions = extensions.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Whether the dotfile and extension rules allow a file, relative to the
+    /// static directory
+    pub(crate) fn allows(&self, relative: &Path) -> bool {
+        self.allows_dir(relative)
+            && (self.allowed_extensions.is_empty()
+                || relative
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| {
+                        self.allowed_extensions
+                            .iter()
+                            .any(|allowed| allowed.eq_ignore_ascii_case(ext))
+                    }))
+    }
+
+    /// Whether the dotfile rule allows a directory, relative to the static
+    /// directory
+    pub(crate) fn allows_dir(&self, relative: &Path) -> bool {
+        self.dotfiles
+            || !relative
+                .components()
+                .any(|component| component.as_os_str().to_string_lossy().starts_with('.'))
+    }
+}
+
+/// Cache-Control value for static files matching a glob pattern
+pub struct CacheRule {
+    /// Glob matched against the file name and the path relative to the
+    /// static directory, e.g. `*.css` or `fonts/**`
+    pub pattern: String,
+    /// Cache-Control header value
+    pub value: String,
+}
+
+impl StaticConfig {
+    /// Serve `directory` at `serve_path`, using precompressed siblings when
+    /// present
+    pub fn new(serve_path: impl Into<String>, directory: impl Into<String>) -> Self {
+        Self {
+            serve_path: serve_path.into(),
+            directory: directory.into(),
+            precompressed: true,
+            compress: false,
+            compress_cache_dir: ".compress-cache".to_string(),
+            cache_rules: Vec::new(),
+            default_cache_control: "no-cache".to_string(),
+            etag: true,
+            last_modified: true,
+            fingerprint: false,
+            manifest: None,
+            spa: false,
+            spa_exclude: Vec::new(),
+            options: StaticOptions::default(),
+            #[cfg(feature = "images")]
+            images: None,
+            #[cfg(feature = "embedded-assets")]
+            embedded: None,
+        }
+    }
+
+    /// Enable or disable serving `.br`/`.gz` siblings
+    pub fn precompressed(mut self, enabled: bool) -> Self {
+        self.precompressed = enabled;
+        self
+    }
+
+    /// Enable or disable compressing files on request. Replaces serving
+    /// `.br`/`.gz` siblings.
+    pub fn compress(mut self, enabled: bool) -> Self {
+        self.compress = enabled;
+        self
+    }
+
+    /// Keep compressed files in `directory` (`.compress-cache` by default)
+    pub fn compress_cache_dir(mut self, directory: impl Into<String>) -> Self {
+        self.compress_cache_dir = directory.into();
+        self
+    }
+
+    /// Add a Cache-Control rule for files matching `pattern`.
+    ///
+    /// Rules are checked in order after the built-in rule marking
+    /// fingerprinted files (`app.3f2a1c9b.css`) as immutable.
+    pub fn cache_control(mut self, pattern: impl Into<String>, value: impl Into<String>) -> Self {
+        self.cache_rules.push(CacheRule {
+            pattern: pattern.into(),
+            value: value.into(),
+        });
+        self
+    }
+
+    /// Set the Cache-Control for files no rule matches (`no-cache` by
+    /// default, so browsers revalidate with the ETag)
+    pub fn default_cache_control(mut self, value: impl Into<String>) -> Self {
+        self.default_cache_control = value.into();
+        self
+    }
+
+    /// Enable or disable ETags
+    pub fn etag(mut self, enabled: bool) -> Self {
+        self.etag = enabled;
+        self
+    }
+
+    /// Enable or disable `Last-Modified`
+    pub fn last_modified(mut self, enabled: bool) -> Self {
+        self.last_modified = enabled;
+        self
+    }
+
+    /// Enable or disable fingerprinted file names, see [`crate::assets`]
+    pub fn fingerprint(mut self, enabled: bool) -> Self {
+        self.fingerprint = enabled;
+        self
+    }
+
+    /// Use a manifest saved with `AssetManifest::save` instead of scanning
+    /// the directory at startup, enables fingerprinting
+    pub fn manifest(mut self, path: impl Into<String>) -> Self {
+        self.fingerprint = true;
+        self.manifest = Some(path.into());
+        self
+    }
+
+    /// Enable or disable the single-page app fallback to `index.html`
+    pub fn spa(mut self, enabled: bool) -> Self {
+        self.spa = enabled;
+        self
+    }
+
+    /// Never answer paths under `prefix` with the single-page app's
+    /// `index.html`, e.g. `/api`
+    pub fn spa_exclude(mut self, prefix: impl Into<String>) -> Self {
+        self.spa_exclude.push(prefix.into());
+        self
+    }
+
+    /// Set which files may be served, see [`StaticOptions`]
+    pub fn options(mut self, options: StaticOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Serve resized images under `_img/{width}x{height}/`, see
+    /// [`crate::images`]. Only applies to files on disk.
+    #[cfg(feature = "images")]
+    pub fn images(mut self, options: crate::images::ImageOptions) -> Self {
+        self.images = Some(options);
+        self
+    }
+
+    /// Serve the files embedded by `E` from memory, see
+    /// [`crate::embedded`]. `directory` is still served in Development when
+    /// it exists.
+    #[cfg(feature = "embedded-assets")]
+    pub fn embedded<E: crate::embedded::RustEmbed>(mut self) -> Self {
+        self.embedded = Some(crate::embedded::EmbeddedFiles::of::<E>());
+        self
+    }
+
+    /// Whether the files are served from memory
+    pub(crate) fn is_embedded(&self) -> bool {
+        #[cfg(feature = "embedded-assets")]
+        return self.embedded.is_some();
+        #[cfg(not(feature = "embedded-assets"))]
+        false
+    }
 }
 
 impl App {
@@ -40,15 +358,26 @@ impl App {
     pub fn new() -> Self {
         Self {
             config: None,
+            settings: None,
+            config_error: None,
             web_routes: None,
             api_routes: None,
-            static_config: Some(StaticConfig {
-                serve_path: "/public".to_string(),
-                directory: "./public".to_string(),
-            }),
-            enable_live_reload: cfg!(debug_assertions),
+            extra_routes: Vec::new(),
+            default_static: Some(StaticConfig::new("/public", "./public")),
+            static_configs: Vec::new(),
+            error_templates: Vec::new(),
+            enable_live_reload: None,
+            live_reload_ignore: Vec::new(),
+            #[cfg(feature = "live-reload")]
+            watcher: live_reload::AppWatcher::default(),
+            shutdown_timeout: None,
+            shutdown_hooks: Vec::new(),
+            startup_hooks: Vec::new(),
+            ready_hooks: Vec::new(),
+            state: Extensions::new(),
             environment: None,
             templates_path: None,
+            template_engine: None,
             docs_path: None,
             openapi_json_path: None,
         }
@@ -61,8 +390,14 @@ impl App {
     }
 
     /// Load config from environment automatically
+    ///
+    /// Settings that can't be parsed fail [`serve`](Self::serve) with
+    /// [`Error::Config`].
     pub fn auto_config(mut self) -> Self {
-        let config = load_config();
+        let config = try_load_config().unwrap_or_else(|err| {
+            self.config_error = Some(err);
+            AppConfig::default()
+        });
         self.environment = Some(config.environment.clone());
 
         // Set templates path if configured
@@ -70,12 +405,31 @@ impl App {
             self.templates_path = Some(config.templates.path.clone());
         }
 
-        // Set static files if configured
-        if !config.templates.static_dir.is_empty() {
-            self.static_config = Some(StaticConfig {
-                serve_path: config.templates.static_path.clone(),
-                directory: config.templates.static_dir.clone(),
-            });
+        // Set static files if configured, unless already set with the builder
+        if !config.templates.static_dir.is_empty() && self.default_static.is_some() {
+            let mut static_config = StaticConfig::new(
+                config.templates.static_path.clone(),
+                config.templates.static_dir.clone(),
+            )
+            .etag(config.templates.static_etag)
+            .last_modified(config.templates.static_last_modified)
+            .fingerprint(config.templates.static_fingerprint);
+
+            if !config.templates.static_manifest.is_empty() {
+                static_config = static_config.manifest(config.templates.static_manifest.clone());
+            }
+            if !config.templates.static_cache_control.is_empty() {
+                static_config = static_config
+                    .default_cache_control(config.templates.static_cache_control.clone());
+            }
+            // `pattern=value` pairs separated by `;`, values may contain commas
+            for rule in config.templates.static_cache_rules.split(';') {
+                if let Some((pattern, value)) = rule.split_once('=') {
+                    static_config = static_config.cache_control(pattern.trim(), value.trim());
+                }
+            }
+
+            self.default_static = Some(static_config);
         }
 
         // Set docs paths if configured
@@ -87,6 +441,7 @@ impl App {
         }
 
         self.config = Some(to_uncovr_config(&config));
+        self.settings = Some(config);
         self
     }
 
@@ -102,6 +457,12 @@ impl App {
         self
     }
 
+    /// Set the template engine (Tera by default)
+    pub fn template_engine(mut self, engine: Engine) -> Self {
+        self.template_engine = Some(engine);
+        self
+    }
+
     /// Set the Swagger docs path
     pub fn docs_path(mut self, path: impl Into<String>) -> Self {
         self.docs_path = Some(path.into());
@@ -168,47 +529,536 @@ impl App {
         self
     }
 
-    /// Enable static file serving with custom paths
-    pub fn static_files(
-        mut self,
-        serve_path: impl Into<String>,
-        directory: impl Into<String>,
-    ) -> Self {
-        self.static_config = Some(StaticConfig {
-            serve_path: serve_path.into(),
-            directory: directory.into(),
-        });
+    /// Merge additional routes, e.g. from `static_files::serve_dir` or
+    /// another module
+    pub fn merge(mut self, routes: ApiRouter) -> Self {
+        self.extra_routes.push(routes);
         self
     }
 
-    /// Disable static file serving
+    /// Serve a static files directory at a path.
+    ///
+    /// Can be called several times to add mounts. The first call replaces the
+    /// default `/public` mount (or the one from `auto_config`).
+    pub fn static_files(self, serve_path: impl Into<String>, directory: impl Into<String>) -> Self {
+        self.static_config(StaticConfig::new(serve_path, directory))
+    }
+
+    /// Serve static files with a custom configuration, adding a mount like
+    /// [`App::static_files`]
+    pub fn static_config(mut self, config: StaticConfig) -> Self {
+        self.default_static = None;
+        self.static_configs.push(config);
+        self
+    }
+
+    /// Serve a single-page app from `directory` at `serve_path`.
+    ///
+    /// Unknown paths without a file extension get `index.html`, so
+    /// client-side routes survive a reload, while missing assets such as
+    /// `app.js` are still `404`. Paths under the API prefix are never
+    /// answered with `index.html`. Unlike [`App::static_files`] the default
+    /// `/public` mount is kept.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use wenzetu::App;
+    ///
+    /// let app = App::new().spa("/admin", "./admin-dist");
+    /// ```
+    pub fn spa(mut self, serve_path: impl Into<String>, directory: impl Into<String>) -> Self {
+        self.static_configs
+            .push(StaticConfig::new(serve_path, directory).spa(true));
+        self
+    }
+
+    /// Disable static file serving, removing the mounts added so far
     pub fn no_static_files(mut self) -> Self {
-        self.static_config = None;
+        self.default_static = None;
+        self.static_configs.clear();
+        self
+    }
+
+    /// Render `name` for `404 Not Found` responses, see
+    /// [`App::error_template`]
+    pub fn not_found_template(self, name: impl Into<String>) -> Self {
+        self.error_template(404, name)
+    }
+
+    /// Render `name` for error responses with `status`, such as missing
+    /// static files or requests no route matches.
+    ///
+    /// Responses rendered by handlers are kept, and paths under the API
+    /// prefix get a JSON error instead. The template gets `status`, `reason`,
+    /// `method`, `path` and `query`.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use wenzetu::App;
+    ///
+    /// let app = App::new()
+    ///     .not_found_template("errors/404.html")
+    ///     .error_template(500, "errors/500.html");
+    /// ```
+    pub fn error_template(mut self, status: u16, name: impl Into<String>) -> Self {
+        self.error_templates.push((status, name.into()));
         self
     }
 
-    /// Enable or disable live reload
+    /// Enable or disable live reload.
+    ///
+    /// Defaults to enabled in `Environment::Development` and disabled in
+    /// Staging and Production, regardless of the build profile.
     pub fn live_reload(mut self, enabled: bool) -> Self {
-        self.enable_live_reload = enabled;
+        self.enable_live_reload = Some(enabled);
         self
     }
 
+    /// Handle to the live reload watcher [`serve`](App::serve) starts, to
+    /// stop or restart it, or read its last error, while the app runs.
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// let app = App::new().auto_config().web(web_routes);
+    /// let watcher = app.watcher();
+    /// tokio::spawn(async move {
+    ///     // ...
+    ///     watcher.set_templates("views/**/*")?;
+    /// });
+    /// app.serve().await?;
+    /// ```
+    #[cfg(feature = "live-reload")]
+    pub fn watcher(&self) -> live_reload::AppWatcher {
+        self.watcher.clone()
+    }
+
+    /// Ignore files matching a glob pattern when watching for live reload,
+    /// e.g. `"*.map"` or `"vendor/**"`
+    pub fn live_reload_ignore(mut self, pattern: impl Into<String>) -> Self {
+        self.live_reload_ignore.push(pattern.into());
+        self
+    }
+
+    /// Shut down on Ctrl+C or SIGTERM: stop accepting connections, wait up
+    /// to `timeout` for open requests, stop the live reload watcher and run
+    /// the [`on_shutdown`](App::on_shutdown) hooks.
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// App::new()
+    ///     .auto_config()
+    ///     .web(web_routes)
+    ///     .graceful_shutdown(Duration::from_secs(10))
+    ///     .serve()
+    ///     .await?;
+    /// ```
+    pub fn graceful_shutdown(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = Some(timeout);
+        self
+    }
+
+    /// Run an async hook after the server stops, e.g. to close a database
+    /// pool. Hooks run in reverse registration order, also when startup
+    /// fails after the [`on_startup`](App::on_startup) hooks ran.
+    ///
+    /// Without [`graceful_shutdown`](App::graceful_shutdown), Ctrl+C ends
+    /// the process without running them.
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// let pool = db_pool.clone();
+    /// App::new()
+    ///     .graceful_shutdown(Duration::from_secs(10))
+    ///     .on_shutdown(move || async move { pool.close().await })
+    /// ```
+    pub fn on_shutdown<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.shutdown_hooks.push(shutdown::hook(hook));
+        self
+    }
+
+    /// Share a value with every web and API route, extracted in handlers
+    /// with [`Shared<T>`](crate::state::Shared). Adding a value of the same
+    /// type again replaces it.
+    ///
+    /// The resolved [`AppConfig`] and a
+    /// [`Templates`](crate::templates::Templates) handle are always
+    /// available.
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// App::new()
+    ///     .state(Db::connect(&url).await?)
+    ///     .web(web_routes)
+    /// ```
+    pub fn state<T>(mut self, state: T) -> Self
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        self.state.insert(state);
+        self
+    }
+
+    /// Run an async hook before the server starts, e.g. to run migrations
+    /// or warm caches. Hooks run in registration order and get the
+    /// resolved configuration; an error aborts startup with
+    /// [`Error::Startup`].
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// App::new()
+    ///     .auto_config()
+    ///     .on_startup(|config| async move {
+    ///         migrations::run(&config.app.name).await
+    ///     })
+    /// ```
+    pub fn on_startup<F, Fut, E>(mut self, hook: F) -> Self
+    where
+        F: FnOnce(AppConfig) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Into<BoxError>,
+    {
+        self.startup_hooks.push(Box::new(move |config| {
+            Box::pin(async move { hook(config).await.map_err(Into::into) })
+        }));
+        self
+    }
+
+    /// Run a hook once the server listens, with the bound address, e.g. to
+    /// print a banner. Hooks run in registration order.
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// App::new()
+    ///     .auto_config()
+    ///     .on_ready(|addr| println!("Listening on http://{}", addr))
+    /// ```
+    pub fn on_ready<F>(mut self, hook: F) -> Self
+    where
+        F: FnOnce(SocketAddr) + Send + 'static,
+    {
+        self.ready_hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Settings from `auto_config`, `with_config` or else the environment,
+    /// with the builder's overrides applied
+    fn resolved_config(&self) -> Result<AppConfig, Error> {
+        let mut config = match (&self.settings, &self.config) {
+            (Some(settings), _) => settings.clone(),
+            // A supplied config replaces the environment
+            (None, Some(_)) => AppConfig::default(),
+            (None, None) => try_load_config()?,
+        };
+        if let Some(uncovr_config) = &self.config {
+            apply_uncovr_config(&mut config, uncovr_config);
+        }
+        if let Some(environment) = &self.environment {
+            config.environment = environment.clone();
+        }
+        if let Some(templates_path) = &self.templates_path {
+            config.templates.path = templates_path.clone();
+        }
+        if let Some(static_cfg) = self.static_configs.first().or(self.default_static.as_ref()) {
+            config.templates.static_path = static_cfg.serve_path.clone();
+            config.templates.static_dir = static_cfg.directory.clone();
+        }
+        if let Some(docs_path) = &self.docs_path {
+            config.docs.docs_path = docs_path.clone();
+        }
+        if let Some(openapi_json_path) = &self.openapi_json_path {
+            config.docs.openapi_json_path = openapi_json_path.clone();
+        }
+        Ok(config)
+    }
+
+    /// Check that no two mounts serve the same path, since one of them
+    /// would be unreachable. Nested mounts such as `/public` and
+    /// `/public/img` are fine, the longer path wins.
+    ///
+    /// Merged routers are opaque, axum reports their conflicting routes
+    /// when the server is assembled.
+    fn check_mounts(&self, resolved: &AppConfig) -> Result<(), Error> {
+        let mut mounts: Vec<(&str, String)> = self
+            .static_configs
+            .iter()
+            .map(|cfg| {
+                (
+                    cfg.serve_path.as_str(),
+                    format!("static files from '{}'", cfg.directory),
+                )
+            })
+            .collect();
+        if let Some(api_config) = &self.api_routes {
+            mounts.push((api_config.path.as_str(), "API routes".to_string()));
+            if let Some(docs_path) = &api_config.docs_path {
+                mounts.push((docs_path.as_str(), "API docs".to_string()));
+            }
+            if let Some(openapi_json_path) = &api_config.openapi_json_path {
+                mounts.push((openapi_json_path.as_str(), "the OpenAPI spec".to_string()));
+            }
+        }
+        #[cfg(feature = "live-reload")]
+        if self.live_reload_enabled(resolved) {
+            mounts.push((live_reload::EVENTS_PATH, "live reload events".to_string()));
+        }
+        #[cfg(not(feature = "live-reload"))]
+        let _ = resolved;
+
+        for (index, (path, name)) in mounts.iter().enumerate() {
+            for (other_path, other_name) in &mounts[index + 1..] {
+                if normalize_path(path) == normalize_path(other_path) {
+                    return Err(Error::config(format!(
+                        "{} and {} are both mounted at '{}'",
+                        name, other_name, path
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether pages get live reload, by default only in Development
+    fn live_reload_enabled(&self, resolved: &AppConfig) -> bool {
+        self.enable_live_reload
+            .unwrap_or(matches!(resolved.environment, Environment::Development))
+    }
+
     /// Build and run the server
-    pub async fn serve(self) -> Result<(), Box<dyn std::error::Error>> {
+    ///
+    /// Fails with [`Error::Config`] for invalid settings, conflicting mounts
+    /// or routes, an asset manifest that can't be read or scanned, or a live
+    /// reload watcher that can't start,
+    /// [`Error::Template`] when templates don't load outside Development,
+    /// [`Error::Startup`] when an [`on_startup`](App::on_startup) hook fails,
+    /// [`Error::Bind`] when the address can't be bound and [`Error::Server`]
+    /// when the server stops with an error.
+    pub async fn serve(mut self) -> Result<(), Error> {
+        let startup_hooks = std::mem::take(&mut self.startup_hooks);
+        let ready_hooks = std::mem::take(&mut self.ready_hooks);
+        let shutdown_hooks = std::mem::take(&mut self.shutdown_hooks);
+        let shutdown_timeout = self.shutdown_timeout;
+        #[cfg(feature = "live-reload")]
+        let watcher = self.watcher.clone();
+
+        // Hooks run before templates, assets and the watcher are set up
+        let resolved = self.resolve()?;
+        for hook in startup_hooks {
+            hook(resolved.clone()).await.map_err(Error::Startup)?;
+        }
+
+        // Startup hooks may have opened resources the shutdown hooks close
+        let (server, listener) = match self.listen(resolved).await {
+            Ok(started) => started,
+            Err(err) => {
+                shutdown::run_hooks(shutdown_hooks).await;
+                return Err(err);
+            }
+        };
+        let local_addr = listener.local_addr()?;
+
+        for hook in ready_hooks {
+            hook(local_addr);
+        }
+
+        let router = server.build().into_router();
+        let result = shutdown::serve(listener, router, shutdown_timeout)
+            .await
+            .map_err(|err| Error::Server(Box::new(err)));
+
+        #[cfg(feature = "live-reload")]
+        watcher.stop();
+        shutdown::run_hooks(shutdown_hooks).await;
+
+        result
+    }
+
+    /// Set the app up, bind the listener and start watching files
+    async fn listen(self, resolved: AppConfig) -> Result<(Server, tokio::net::TcpListener), Error> {
+        #[cfg(feature = "live-reload")]
+        let watcher = self.watcher.clone();
+        let prepared = self.prepare(resolved)?;
+
+        let listener = tokio::net::TcpListener::bind(&prepared.address)
+            .await
+            .map_err(Error::Bind)?;
+        // The development runner starts the app on port 0 and waits for
+        // the port it got
+        if let Some(path) = std::env::var_os(DEV_ADDR_FILE) {
+            report_address(&listener, Path::new(&path)).map_err(Error::Io)?;
+        }
+
+        // Watch templates and static files until the server stops
+        #[cfg(feature = "live-reload")]
+        if let Some(watch_config) = prepared.watch {
+            watcher.start(watch_config).map_err(|err| {
+                Error::config(format!("failed to start the live reload watcher: {}", err))
+            })?;
+        }
+
+        Ok((prepared.server, listener))
+    }
+
+    /// Build the app into a router without binding a socket.
+    ///
+    /// Use it to drive requests in-process in tests, or to mount the app in
+    /// another server. Setup is the same as [`App::serve`], except that
+    /// files are not watched for live reload.
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// use tower::ServiceExt;
+    ///
+    /// let router = App::new().web(web_routes).build()?;
+    /// let response = router
+    ///     .oneshot(Request::get("/").body(Body::empty())?)
+    ///     .await?;
+    /// assert_eq!(response.status(), StatusCode::OK);
+    /// ```
+    pub fn build(mut self) -> Result<Router, Error> {
+        let resolved = self.resolve()?;
+        Ok(self.prepare(resolved)?.server.build().into_router())
+    }
+
+    /// Check the configuration and resolve the settings, without side
+    /// effects
+    fn resolve(&mut self) -> Result<AppConfig, Error> {
+        if let Some(err) = self.config_error.take() {
+            return Err(err);
+        }
+        if let Some(default_static) = self.default_static.take() {
+            self.static_configs.insert(0, default_static);
+        }
+        let resolved = self.resolved_config()?;
+        self.check_mounts(&resolved)?;
+
+        // Without an allowlist every image could be requested at any size
+        #[cfg(feature = "images")]
+        if let Some(static_cfg) = self.static_configs.iter().find(|cfg| {
+            cfg.images
+                .as_ref()
+                .is_some_and(|images| images.sizes.is_empty())
+        }) {
+            return Err(Error::config(format!(
+                "resized images at '{}' need at least one size, add them with ImageOptions::size()",
+                static_cfg.serve_path
+            )));
+        }
+
+        // Keep unknown API paths from falling back to a single-page app
+        if let Some(api_config) = &self.api_routes {
+            for static_cfg in self.static_configs.iter_mut().filter(|cfg| cfg.spa) {
+                static_cfg.spa_exclude.push(api_config.path.clone());
+            }
+        }
+
+        Ok(resolved)
+    }
+
+    /// Initialize templates and assets and assemble the server
+    fn prepare(mut self, resolved: AppConfig) -> Result<Prepared, Error> {
+        let environment = resolved.environment.clone();
+        let live_reload = self.live_reload_enabled(&resolved);
+
+        if live_reload && !cfg!(feature = "live-reload") {
+            eprintln!("Live reload requested but the `live-reload` feature is disabled");
+        }
+        templates::init_live_reload(live_reload);
+
         // Initialize templates with custom path if provided
         if let Some(template_path) = &self.templates_path {
             templates::init_templates(template_path);
         }
+        if let Some(engine) = self.template_engine {
+            templates::init_engine(engine);
+        }
+        // Templates loaded by an earlier app or render keep their path and
+        // engine until reloaded
+        if (self.templates_path.is_some() || self.template_engine.is_some())
+            && templates::templates_loaded()
+        {
+            let _ = templates::reload_templates();
+        }
 
-        let mut config = self.config.unwrap_or_else(|| {
-            let cfg = load_config();
-            to_uncovr_config(&cfg)
-        });
+        // Development shows template errors on the page until they're fixed
+        if !matches!(environment, Environment::Development) {
+            templates::check_templates()?;
+        }
+
+        // Directory listings are a development aid
+        if !matches!(environment, Environment::Development) {
+            for static_cfg in &mut self.static_configs {
+                static_cfg.options.autoindex = false;
+            }
+        }
+
+        // Serve embedded files from disk in Development so edits show up
+        // without a rebuild
+        #[cfg(feature = "embedded-assets")]
+        if matches!(environment, Environment::Development) {
+            for static_cfg in &mut self.static_configs {
+                if Path::new(&static_cfg.directory).is_dir() {
+                    static_cfg.embedded = None;
+                }
+            }
+        }
+
+        #[cfg(not(feature = "compression"))]
+        for static_cfg in self
+            .static_configs
+            .iter()
+            .filter(|cfg| cfg.compress && !cfg.is_embedded())
+        {
+            eprintln!(
+                "Compression requested for {} but the `compression` feature is disabled",
+                static_cfg.directory
+            );
+        }
+
+        // Register static URLs for `asset()`
+        let manifests = self
+            .static_configs
+            .iter()
+            .map(load_manifest)
+            .collect::<Result<Vec<_>, _>>()?;
+        for manifest in manifests {
+            assets::init_assets(manifest);
+        }
+
+        #[cfg(feature = "live-reload")]
+        let watch = if live_reload {
+            let mut watch_config = live_reload::WatchConfig {
+                templates: Some(templates::get_template_path()),
+                static_dirs: self
+                    .static_configs
+                    .iter()
+                    .filter(|cfg| !cfg.is_embedded())
+                    .map(|cfg| (cfg.serve_path.clone(), cfg.directory.clone()))
+                    .collect(),
+                ..live_reload::WatchConfig::default()
+            };
+            watch_config
+                .ignore
+                .extend(self.live_reload_ignore.iter().cloned());
+            Some(watch_config)
+        } else {
+            None
+        };
+
+        let mut config = match self.config {
+            Some(config) => config,
+            None => to_uncovr_config(&resolved),
+        };
 
         // Override environment if specified
         if let Some(env) = self.environment {
             config = config.environment(env);
         }
+        // `resolved` already holds the address of a supplied config
+        let address = format!("{}:{}", resolved.app.address, resolved.app.port);
 
         let mut server = Server::new().with_config(config);
 
@@ -218,26 +1068,120 @@ impl App {
         }
 
         // Add API routes
+        let api_path = self.api_routes.as_ref().map(|cfg| cfg.path.clone());
         if let Some(api_config) = self.api_routes {
             server = server.nest(&api_config.path, api_config.routes);
         }
 
+        // Add extra routes
+        for routes in self.extra_routes {
+            server = server.merge(routes);
+        }
+
         // Add static files
-        if let Some(static_cfg) = self.static_config {
-            let static_routes =
-                static_files::serve_dir(&static_cfg.serve_path, &static_cfg.directory);
-            server = server.merge(static_routes);
+        for static_cfg in &self.static_configs {
+            server = server.merge(static_files::serve_static(static_cfg));
         }
 
-        // Add live reload in development
-        #[cfg(debug_assertions)]
-        if self.enable_live_reload {
-            use crate::templates::live_reload_layer;
-            server = server.layer(live_reload_layer());
+        // Share state with all routes, values added with `state` win
+        let mut state = Extensions::new();
+        state.insert(resolved.clone());
+        state.insert(templates::Templates);
+        state.extend(self.state);
+        server = server.layer(StateLayer::new(state));
+
+        // Render error templates for the errors Wenzetu answers with
+        if !self.error_templates.is_empty() {
+            // A static mount at the root is already the fallback
+            let root_mount = self
+                .static_configs
+                .iter()
+                .any(|cfg| normalize_path(&cfg.serve_path) == "/");
+            if !root_mount {
+                server = server.merge(ApiRouter::new().fallback(error_pages::not_found));
+            }
+            let mut error_pages = ErrorPagesLayer::new();
+            for (status, name) in self.error_templates {
+                error_pages = error_pages.template(status, name);
+            }
+            if let Some(api_path) = api_path {
+                error_pages = error_pages.api_prefix(api_path);
+            }
+            server = server.layer(error_pages);
         }
 
-        server.build().serve().await?;
-        Ok(())
+        // Add live reload
+        #[cfg(feature = "live-reload")]
+        if live_reload {
+            server = server
+                .merge(live_reload::routes())
+                .layer(live_reload::live_reload_layer());
+        }
+
+        Ok(Prepared {
+            server,
+            address,
+            #[cfg(feature = "live-reload")]
+            watch,
+        })
+    }
+}
+
+/// Async hook run before the server starts, see [`App::on_startup`]
+type StartupHook =
+    Box<dyn FnOnce(AppConfig) -> Pin<Box<dyn Future<Output = Result<(), BoxError>> + Send>> + Send>;
+
+/// Hook run once the server listens, see [`App::on_ready`]
+type ReadyHook = Box<dyn FnOnce(SocketAddr) + Send>;
+
+/// An app ready to serve, see [`App::build`]
+struct Prepared {
+    server: Server,
+    /// Address to listen on, from the final server configuration
+    address: String,
+    /// What to watch for live reload, if enabled
+    #[cfg(feature = "live-reload")]
+    watch: Option<live_reload::WatchConfig>,
+}
+
+/// A mount path with one leading slash and no empty segments, so `/api/`,
+/// `api` and `//api` compare equal
+fn normalize_path(path: &str) -> String {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    format!("/{}", segments.join("/"))
+}
+
+/// Write the address of `listener` to `path`, renaming it into place so
+/// the development runner never reads a partial address
+fn report_address(listener: &tokio::net::TcpListener, path: &Path) -> std::io::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".tmp");
+    std::fs::write(&partial, listener.local_addr()?.to_string())?;
+    std::fs::rename(&partial, path)
+}
+
+/// Asset manifest of a static mount, empty when fingerprinting is off
+fn load_manifest(config: &StaticConfig) -> Result<AssetManifest, Error> {
+    if !config.fingerprint {
+        return Ok(AssetManifest::empty(&config.serve_path));
+    }
+
+    #[cfg(feature = "embedded-assets")]
+    if let (None, Some(files)) = (&config.manifest, &config.embedded) {
+        return Ok(AssetManifest::from_embedded(
+            &config.serve_path,
+            files,
+            &config.options,
+        ));
+    }
+
+    match &config.manifest {
+        Some(path) => AssetManifest::load(&config.serve_path, path).map_err(Error::config),
+        None => AssetManifest::scan(&config.serve_path, &config.directory, &config.options)
+            .map_err(|err| Error::Config {
+                message: format!("failed to fingerprint {}", config.directory),
+                source: Some(Box::new(err)),
+            }),
     }
 }
 
@@ -248,10 +1192,7 @@ impl Default for App {
 }
 
 /// Quick setup for full-stack apps (web + api)
-pub async fn fullstack(
-    web_routes: ApiRouter,
-    api_routes: ApiRouter,
-) -> Result<(), Box<dyn std::error::Error>> {
+pub async fn fullstack(web_routes: ApiRouter, api_routes: ApiRouter) -> Result<(), Error> {
     App::new()
         .auto_config()
         .web(web_routes)
@@ -261,12 +1202,12 @@ pub async fn fullstack(
 }
 
 /// Quick setup for web-only apps
-pub async fn web(routes: ApiRouter) -> Result<(), Box<dyn std::error::Error>> {
+pub async fn web(routes: ApiRouter) -> Result<(), Error> {
     App::new().auto_config().web(routes).serve().await
 }
 
 /// Quick setup for API-only apps
-pub async fn api(routes: ApiRouter) -> Result<(), Box<dyn std::error::Error>> {
+pub async fn api(routes: ApiRouter) -> Result<(), Error> {
     App::new()
         .auto_config()
         .api("/api", routes)
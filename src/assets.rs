@@ -0,0 +1,385 @@
+//! Asset fingerprinting
+//!
+//! Maps static files to content-hashed names such as `app.3f2a1c9b.css` so
+//! they can be cached forever. Hashed names are served from the original
+//! files, nothing is written to the static directory. Templates resolve
+//! URLs through the manifest with `{{ asset(path="app.css") }}`.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, RwLock};
+
+use serde_json::Value;
+
+use crate::app::StaticOptions;
+use crate::static_files::content_hash;
+
+/// Manifests of every static mount, see [`init_assets`]
+static ASSETS: LazyLock<RwLock<Vec<AssetManifest>>> = LazyLock::new(|| RwLock::new(Vec::new()));
+
+/// Length of the hash added to file names
+const HASH_LEN: usize = 8;
+
+/// Mapping from static files to their fingerprinted names
+#[derive(Debug, Clone, Default)]
+pub struct AssetManifest {
+    serve_path: String,
+    /// Directory the manifest was scanned from, rescanned on changes
+    directory: Option<PathBuf>,
+    /// Access rules of the mount, files they deny are not hashed
+    options: StaticOptions,
+    /// `app.css` -> `app.3f2a1c9b.css`, relative to the static directory
+    files: BTreeMap<String, String>,
+    /// `app.3f2a1c9b.css` -> `app.css`
+    originals: HashMap<String, String>,
+}
+
+impl AssetManifest {
+    /// A manifest without fingerprinted files, `asset()` then only prefixes
+    /// paths with `serve_path`
+    pub fn empty(serve_path: impl Into<String>) -> Self {
+        Self {
+            serve_path: serve_path.into().trim_end_matches('/').to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// Hash every file in `directory` that `options` allows serving.
+    ///
+    /// Files without an extension, already fingerprinted files and `.br`/`.gz`
+    /// siblings keep their name.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use wenzetu::app::StaticOptions;
+    /// use wenzetu::assets::AssetManifest;
+    ///
+    /// let manifest = AssetManifest::scan("/public", "./public", &StaticOptions::default()).unwrap();
+    /// println!("{}", manifest.url("app.css")); // /public/app.3f2a1c9b.css
+    /// ```
+    pub fn scan(
+        serve_path: impl Into<String>,
+        directory: impl AsRef<Path>,
+        options: &StaticOptions,
+    ) -> std::io::Result<Self> {
+        let directory = directory.as_ref();
+        let mut manifest = Self::empty(serve_path);
+        manifest.directory = Some(directory.to_path_buf());
+        manifest.options = options.clone();
+
+        let fingerprinted = glob::Pattern::new(crate::static_files::FINGERPRINTED)
+            .expect("FINGERPRINTED is a valid pattern");
+        let root = std::fs::canonicalize(directory)?;
+        // Directories reached twice through symlinks are scanned once
+        let mut visited = HashSet::new();
+        let mut pending = vec![directory.to_path_buf()];
+
+        while let Some(dir) = pending.pop() {
+            if !visited.insert(std::fs::canonicalize(&dir)?) {
+                continue;
+            }
+            for entry in std::fs::read_dir(&dir)? {
+                let entry = entry?;
+                let path = entry.path();
+                let Ok(relative) = path.strip_prefix(directory) else {
+                    continue;
+                };
+
+                let mut file_type = entry.file_type()?;
+                if file_type.is_symlink() {
+                    // Skip what the static service denies: every symlink
+                    // without `follow_symlinks`, broken ones and those
+                    // leading outside the directory
+                    let target = std::fs::canonicalize(&path);
+                    if !options.follow_symlinks || !target.is_ok_and(|t| t.starts_with(&root)) {
+                        continue;
+                    }
+                    file_type = std::fs::metadata(&path)?.file_type();
+                }
+                if file_type.is_dir() {
+                    if options.allows_dir(relative) {
+                        pending.push(path);
+                    }
+                    continue;
+                }
+                if !file_type.is_file() || !options.allows(relative) {
+                    continue;
+                }
+
+                let name = relative.to_string_lossy().replace('\\', "/");
+                if fingerprinted.matches(&name) || is_compressed_sibling(&path) {
+                    continue;
+                }
+
+                let Some(hashed) = hashed_name(&name, &std::fs::read(&path)?) else {
+                    continue;
+                };
+                manifest.insert(name, hashed);
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    /// Hash every embedded file, skipping the same files as
+    /// [`AssetManifest::scan`]
+    #[cfg(feature = "embedded-assets")]
+    pub fn from_embedded(
+        serve_path: impl Into<String>,
+        files: &crate::embedded::EmbeddedFiles,
+        options: &StaticOptions,
+    ) -> Self {
+        let mut manifest = Self::empty(serve_path);
+        let fingerprinted = glob::Pattern::new(crate::static_files::FINGERPRINTED)
+            .expect("FINGERPRINTED is a valid pattern");
+
+        for name in files.names() {
+            let compressed_sibling = name
+                .strip_suffix(".br")
+                .or_else(|| name.strip_suffix(".gz"))
+                .is_some_and(|source| files.get(source).is_some());
+            if fingerprinted.matches(&name)
+                || compressed_sibling
+                || !options.allows(Path::new(&*name))
+            {
+                continue;
+            }
+
+            let Some(file) = files.get(&name) else {
+                continue;
+            };
+            if let Some(hashed) = hashed_name(&name, &file.data) {
+                manifest.insert(name.into_owned(), hashed);
+            }
+        }
+
+        manifest
+    }
+
+    /// Load a manifest written by [`AssetManifest::save`], e.g. generated at
+    /// build time
+    pub fn load(serve_path: impl Into<String>, path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+        let files: BTreeMap<String, String> = serde_json::from_str(&source)
+            .map_err(|err| format!("invalid manifest {}: {}", path.display(), err))?;
+
+        let mut manifest = Self::empty(serve_path);
+        for (name, hashed) in files {
+            manifest.insert(name, hashed);
+        }
+        Ok(manifest)
+    }
+
+    /// Write the manifest as JSON, `{"app.css": "app.3f2a1c9b.css"}`
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let json = serde_json::to_string_pretty(&self.files).map_err(std::io::Error::other)?;
+        std::fs::write(path, json)
+    }
+
+    /// Path the static files are served at
+    pub fn serve_path(&self) -> &str {
+        &self.serve_path
+    }
+
+    /// Fingerprinted name of a file, relative to the static directory
+    pub fn get(&self, path: &str) -> Option<&str> {
+        self.files
+            .get(path.trim_start_matches('/'))
+            .map(String::as_str)
+    }
+
+    /// URL of a file, fingerprinted when it is in the manifest
+    pub fn url(&self, path: &str) -> String {
+        let path = path.trim_start_matches('/');
+        format!("{}/{}", self.serve_path, self.get(path).unwrap_or(path))
+    }
+
+    /// Original name of a fingerprinted file
+    pub fn original(&self, hashed: &str) -> Option<&str> {
+        self.originals.get(hashed).map(String::as_str)
+    }
+
+    fn insert(&mut self, name: String, hashed: String) {
+        self.originals.insert(hashed.clone(), name.clone());
+        self.files.insert(name, hashed);
+    }
+}
+
+/// Register the manifest of a static mount, replacing the previous manifest
+/// for the same serve path
+pub fn init_assets(manifest: AssetManifest) {
+    if let Ok(mut assets) = ASSETS.write() {
+        assets.retain(|existing| existing.serve_path != manifest.serve_path);
+        assets.push(manifest);
+    }
+}
+
+/// Scan the static directories again after their files changed
+pub fn rescan_assets() {
+    let Ok(mut assets) = ASSETS.write() else {
+        return;
+    };
+    for manifest in assets.iter_mut() {
+        let Some(directory) = manifest.directory.clone() else {
+            continue;
+        };
+        let options = manifest.options.clone();
+        match AssetManifest::scan(manifest.serve_path.clone(), &directory, &options) {
+            Ok(scanned) => *manifest = scanned,
+            Err(err) => eprintln!("Failed to scan {}: {}", directory.display(), err),
+        }
+    }
+}
+
+/// Resolve a static file to its URL, e.g. `app.css` to
+/// `/public/app.3f2a1c9b.css`.
+///
+/// The first manifest containing the file wins. Unknown files are served
+/// from the first static mount, or returned unchanged without one.
+pub fn asset_url(path: &str) -> String {
+    let Ok(assets) = ASSETS.read() else {
+        return path.to_string();
+    };
+    assets
+        .iter()
+        .find(|manifest| manifest.get(path).is_some())
+        .or_else(|| assets.first())
+        .map(|manifest| manifest.url(path))
+        .unwrap_or_else(|| path.to_string())
+}
+
+/// Original file behind a fingerprinted request path of a static mount
+pub(crate) fn original_path(serve_path: &str, hashed: &str) -> Option<String> {
+    let assets = ASSETS.read().ok()?;
+    assets
+        .iter()
+        .find(|manifest| manifest.serve_path == serve_path.trim_end_matches('/'))?
+        .original(hashed)
+        .map(str::to_string)
+}
+
+/// Tera function resolving asset URLs: `{{ asset(path="app.css") }}`
+pub struct AssetFunction;
+
+impl tera::Function for AssetFunction {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let path = args
+            .get("path")
+            .and_then(Value::as_str)
+            .ok_or_else(|| tera::Error::msg("Function `asset` needs a `path` string argument"))?;
+        Ok(Value::String(asset_url(path)))
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+/// `css/app.css` -> `css/app.3f2a1c9b.css`, `None` without an extension
+fn hashed_name(name: &str, content: &[u8]) -> Option<String> {
+    let (stem, extension) = name.rsplit_once('.')?;
+    if stem.is_empty() || stem.ends_with('/') || extension.contains('/') {
+        return None;
+    }
+    let hash = content_hash(content);
+    Some(format!("{}.{}.{}", stem, &hash[..HASH_LEN], extension))
+}
+
+/// Whether `path` is an `app.css.br`/`app.css.gz` sibling of another file
+fn is_compressed_sibling(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == "br" || ext == "gz")
+        && path.with_extension("").is_file()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan(directory: &Path, options: &StaticOptions) -> AssetManifest {
+        AssetManifest::scan("/public", directory, options).unwrap()
+    }
+
+    #[test]
+    fn maps_files_to_hashed_urls() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("app.css"), "body {}").unwrap();
+        std::fs::create_dir_all(dir.path().join("js/vendor")).unwrap();
+        std::fs::write(dir.path().join("js/vendor/lib.js"), "let x;").unwrap();
+        std::fs::write(dir.path().join("LICENSE"), "MIT").unwrap();
+
+        let manifest = scan(dir.path(), &StaticOptions::default());
+
+        assert_eq!(manifest.get("app.css"), Some("app.62368a1a.css"));
+        assert_eq!(manifest.url("/app.css"), "/public/app.62368a1a.css");
+        assert_eq!(
+            manifest.url("js/vendor/lib.js"),
+            "/public/js/vendor/lib.d1dd7ab9.js"
+        );
+        assert_eq!(
+            manifest.original("js/vendor/lib.d1dd7ab9.js"),
+            Some("js/vendor/lib.js")
+        );
+        // Files without an extension keep their name
+        assert_eq!(manifest.get("LICENSE"), None);
+    }
+
+    #[test]
+    fn missing_files_pass_through() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = scan(dir.path(), &StaticOptions::default());
+
+        assert_eq!(manifest.get("missing.js"), None);
+        assert_eq!(manifest.url("missing.js"), "/public/missing.js");
+        assert_eq!(manifest.original("missing.js"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn scan_follows_symlinks_like_the_static_service() {
+        use std::os::unix::fs::symlink;
+
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("secret.txt"), "secret").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("css")).unwrap();
+        std::fs::write(dir.path().join("css/app.css"), "body {}").unwrap();
+        symlink(dir.path().join("css/app.css"), dir.path().join("alias.css")).unwrap();
+        symlink(
+            outside.path().join("secret.txt"),
+            dir.path().join("leak.txt"),
+        )
+        .unwrap();
+        // A loop back to the root
+        symlink(dir.path(), dir.path().join("css/again")).unwrap();
+
+        let manifest = scan(dir.path(), &StaticOptions::default());
+        assert!(manifest.get("css/app.css").is_some());
+        assert!(manifest.get("alias.css").is_some());
+        assert_eq!(manifest.get("leak.txt"), None);
+
+        let manifest = scan(dir.path(), &StaticOptions::default().follow_symlinks(false));
+        assert!(manifest.get("css/app.css").is_some());
+        assert_eq!(manifest.get("alias.css"), None);
+    }
+
+    #[test]
+    fn scan_skips_files_the_options_deny() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(".env"), "KEY=secret").unwrap();
+        std::fs::create_dir(dir.path().join(".git")).unwrap();
+        std::fs::write(dir.path().join(".git/config.txt"), "[core]").unwrap();
+        std::fs::write(dir.path().join("app.css"), "body {}").unwrap();
+        std::fs::write(dir.path().join("dump.sql"), "DROP TABLE users;").unwrap();
+
+        let options = StaticOptions::default().allowed_extensions(["css"]);
+        let manifest = AssetManifest::scan("/public", dir.path(), &options).unwrap();
+
+        assert!(manifest.get("app.css").is_some());
+        for name in [".env", ".git/config.txt", "dump.sql"] {
+            assert_eq!(manifest.get(name), None, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,129 @@
+//! Wenzetu command line
+//!
+//! ```text
+//! wenzetu dev [--addr ADDR] [--watch PATH]... [-- CARGO_ARGS...]
+//! ```
+
+use wenzetu::dev::{DevConfig, run};
+
+const USAGE: &str = "\
+Usage: wenzetu dev [OPTIONS] [-- CARGO_ARGS...]
+
+Rebuild and restart the app in the current directory when sources change.
+
+Options:
+  --addr ADDR     Address to listen on (default: APP.ADDRESS:APP.PORT)
+  --watch PATH    File or directory to watch, can be repeated (default: src, Cargo.toml)
+  -h, --help      Print this help
+
+Arguments after `--` are passed to `cargo build` and `cargo run`, e.g.
+`wenzetu dev -- --bin web`.";
+
+#[tokio::main]
+async fn main() {
+    let config = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(config)) => config,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(err) = run(config).await {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+/// Parse arguments, returning `None` when help was requested
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<DevConfig>, String> {
+    match args.next().as_deref() {
+        Some("dev") => {}
+        Some("-h" | "--help") | None => return Ok(None),
+        Some(command) => return Err(format!("unknown command `{}`", command)),
+    }
+
+    let mut config = DevConfig::default();
+    let mut watch = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--addr" => config.addr = args.next().ok_or("`--addr` needs a value")?,
+            "--watch" => watch.push(args.next().ok_or("`--watch` needs a value")?),
+            "-h" | "--help" => return Ok(None),
+            "--" => {
+                config.cargo_args = args.by_ref().collect();
+                break;
+            }
+            _ => return Err(format!("unexpected argument `{}`", arg)),
+        }
+    }
+
+    if !watch.is_empty() {
+        config.watch = watch;
+    }
+    Ok(Some(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<DevConfig>, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_dev_options() {
+        let config = parse(&[
+            "dev",
+            "--addr",
+            "0.0.0.0:3000",
+            "--watch",
+            "src",
+            "--watch",
+            "templates",
+            "--",
+            "--bin",
+            "web",
+        ])
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(config.addr, "0.0.0.0:3000");
+        assert_eq!(config.watch, ["src", "templates"]);
+        assert_eq!(config.cargo_args, ["--bin", "web"]);
+    }
+
+    #[test]
+    fn keeps_the_defaults_without_options() {
+        let config = parse(&["dev"]).unwrap().unwrap();
+
+        assert_eq!(config.watch, ["src", "Cargo.toml"]);
+        assert!(config.cargo_args.is_empty());
+    }
+
+    #[test]
+    fn help_is_not_an_error() {
+        for args in [&[][..], &["--help"], &["dev", "-h"]] {
+            assert!(parse(args).unwrap().is_none(), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_arguments() {
+        assert_eq!(parse(&["serve"]).unwrap_err(), "unknown command `serve`");
+        assert_eq!(
+            parse(&["dev", "--port"]).unwrap_err(),
+            "unexpected argument `--port`"
+        );
+        assert_eq!(
+            parse(&["dev", "--addr"]).unwrap_err(),
+            "`--addr` needs a value"
+        );
+    }
+}
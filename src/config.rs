@@ -6,8 +6,10 @@ use config::{Config, Environment};
 use serde::Deserialize;
 use uncovr::config::Environment as UncovREnvironment;
 
+use crate::error::Error;
+
 /// Application configuration
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, Default)]
 #[serde(default)]
 pub struct AppConfig {
     /// Application configuration
@@ -46,6 +48,18 @@ pub struct Templates {
     pub static_dir: String,
     /// Static files serve path
     pub static_path: String,
+    /// Cache-Control for static files no rule matches
+    pub static_cache_control: String,
+    /// Cache-Control rules as `pattern=value` pairs separated by `;`
+    pub static_cache_rules: String,
+    /// Send ETags for static files
+    pub static_etag: bool,
+    /// Send Last-Modified for static files
+    pub static_last_modified: bool,
+    /// Serve static files under content-hashed names
+    pub static_fingerprint: bool,
+    /// Asset manifest generated at build time
+    pub static_manifest: String,
 }
 
 /// Documentation configuration
@@ -58,17 +72,6 @@ pub struct Docs {
     pub openapi_json_path: String,
 }
 
-impl Default for AppConfig {
-    fn default() -> Self {
-        Self {
-            app: App::default(),
-            environment: UncovREnvironment::default(),
-            templates: Templates::default(),
-            docs: Docs::default(),
-        }
-    }
-}
-
 impl Default for App {
     fn default() -> Self {
         Self {
@@ -87,6 +90,12 @@ impl Default for Templates {
             path: "templates/**/*".to_string(),
             static_dir: "./public".to_string(),
             static_path: "/public".to_string(),
+            static_cache_control: String::new(),
+            static_cache_rules: String::new(),
+            static_etag: true,
+            static_last_modified: true,
+            static_fingerprint: false,
+            static_manifest: String::new(),
         }
     }
 }
@@ -102,7 +111,8 @@ impl Default for Docs {
 
 /// Load configuration from environment variables.
 ///
-/// Automatically loads .env file if present.
+/// Automatically loads .env file if present. Falls back to the defaults when
+/// the settings can't be read, see [`try_load_config`] to get the error.
 ///
 /// # Example
 /// ```rust
@@ -112,19 +122,38 @@ impl Default for Docs {
 /// println!("Server: {}:{}", config.app.address, config.app.port);
 /// ```
 pub fn load_config() -> AppConfig {
+    try_load_config().unwrap_or_else(|err| {
+        eprintln!("{}\nUsing the default configuration", err.report());
+        AppConfig::default()
+    })
+}
+
+/// Load configuration from environment variables, failing with
+/// [`Error::Config`] when a setting can't be parsed, e.g. `APP.PORT=http`.
+///
+/// # Example
+/// ```rust
+/// use wenzetu::config::try_load_config;
+///
+/// let config = try_load_config()?;
+/// # Ok::<(), wenzetu::Error>(())
+/// ```
+pub fn try_load_config() -> Result<AppConfig, Error> {
     // Load .env file if it exists
-    let _ = dotenvy::dotenv();
+    match dotenvy::dotenv() {
+        Err(err) if !err.not_found() => {
+            return Err(Error::Config {
+                message: "failed to read .env".to_string(),
+                source: Some(Box::new(err)),
+            });
+        }
+        _ => {}
+    }
 
     let source = Environment::default().separator(".");
 
-    let config = Config::builder().add_source(source).build();
-
-    match config {
-        Ok(cfg) => cfg
-            .try_deserialize::<AppConfig>()
-            .unwrap_or_else(|_| AppConfig::default()),
-        Err(_) => AppConfig::default(),
-    }
+    let config = Config::builder().add_source(source).build()?;
+    Ok(config.try_deserialize::<AppConfig>()?)
 }
 
 /// Create uncovr AppConfig from loaded configuration.
@@ -144,3 +173,24 @@ pub fn to_uncovr_config(config: &AppConfig) -> uncovr::config::AppConfig {
         .environment(config.environment.clone())
         .bind(addr)
 }
+
+/// Apply the settings of an uncovr AppConfig, the reverse of
+/// [`to_uncovr_config`], so a config passed to `App::with_config` is what
+/// handlers see.
+pub(crate) fn apply_uncovr_config(
+    config: &mut AppConfig,
+    uncovr_config: &uncovr::config::AppConfig,
+) {
+    config.app.name = uncovr_config.name.clone();
+    config.app.version = uncovr_config.version.clone();
+    config.app.description = uncovr_config.description.clone();
+    config.environment = uncovr_config.environment.clone();
+
+    match uncovr_config.bind_address.rsplit_once(':') {
+        Some((address, port)) if port.parse::<u16>().is_ok() => {
+            config.app.address = address.to_string();
+            config.app.port = port.parse().unwrap_or(config.app.port);
+        }
+        _ => config.app.address = uncovr_config.bind_address.clone(),
+    }
+}
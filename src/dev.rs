@@ -0,0 +1,410 @@
+//! Development runner
+//!
+//! Rebuilds and restarts the app when its Rust sources change. The runner
+//! owns the public listening socket and forwards connections to the app
+//! running on an internal port, so requests made during a restart wait for
+//! the new process instead of failing. The app picks a free port itself and
+//! reports it back, and once it accepts connections the runner asks it to
+//! send one live reload event, refreshing pages whose live reload request was
+//! waiting for the restart.
+
+use std::hash::{BuildHasher, RandomState};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use notify_debouncer_mini::notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{DebounceEventResult, DebouncedEvent, Debouncer, new_debouncer};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::process::{Child, Command};
+use tokio::sync::{mpsc, watch};
+
+use crate::app::DEV_ADDR_FILE;
+use crate::config::load_config;
+use crate::live_reload::{DEV_RELOAD_PATH, DEV_TOKEN, DEV_TOKEN_HEADER, ModifiedTimes};
+
+/// How long a connection waits for the app to come back up
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long the app gets to stop after `SIGTERM` before it is killed
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time given to connections queued during a restart to reach the new
+/// process before the reload event is sent
+const RELOAD_DELAY: Duration = Duration::from_millis(200);
+
+/// Development runner configuration
+#[derive(Debug, Clone)]
+pub struct DevConfig {
+    /// Public address the runner listens on
+    pub addr: String,
+    /// Files and directories whose changes trigger a rebuild
+    pub watch: Vec<String>,
+    /// Extra arguments for `cargo build` and `cargo run`, e.g. `--bin web`
+    pub cargo_args: Vec<String>,
+    /// Debounce delay for source changes
+    pub debounce: Duration,
+}
+
+impl Default for DevConfig {
+    fn default() -> Self {
+        let config = load_config();
+        Self {
+            addr: format!("{}:{}", config.app.address, config.app.port),
+            watch: vec!["src".to_string(), "Cargo.toml".to_string()],
+            cargo_args: Vec::new(),
+            debounce: Duration::from_millis(200),
+        }
+    }
+}
+
+/// Build and run the app, restarting it whenever sources change.
+///
+/// The app is started with `APP.ADDRESS=127.0.0.1` and `APP.PORT=0`, so it
+/// must load its address through `auto_config` or `load_config`, and serve
+/// with [`App::serve`](crate::App::serve), which reports the port it got back
+/// to the runner.
+///
+/// # Example
+/// ```rust,no_run
+/// use wenzetu::dev::{DevConfig, run};
+///
+/// #[tokio::main]
+/// async fn main() -> std::io::Result<()> {
+///     run(DevConfig::default()).await
+/// }
+/// ```
+pub async fn run(config: DevConfig) -> std::io::Result<()> {
+    let listener = TcpListener::bind(&config.addr).await?;
+    println!("wenzetu dev: listening on http://{}", config.addr);
+
+    let (upstream_tx, upstream_rx) = watch::channel::<Option<SocketAddr>>(None);
+    tokio::spawn(accept(listener, upstream_rx));
+
+    // The watcher stops when dropped, keep it for the lifetime of the runner
+    let (_watcher, mut changes) = watch_sources(&config)?;
+    let token = format!("{:016x}", RandomState::new().hash_one(std::process::id()));
+    let addr_file = std::env::temp_dir().join(format!("wenzetu-dev-{}.addr", std::process::id()));
+    let mut child = None;
+
+    loop {
+        if build(&config.cargo_args).await? {
+            // New connections wait until the new process is ready
+            upstream_tx.send_replace(None);
+            if let Some(mut old) = child.take() {
+                stop(&mut old).await;
+            }
+            let new = start(&config.cargo_args, &addr_file, &token).await?;
+            let child = child.insert(new);
+
+            match wait_ready(child, &addr_file).await {
+                Some(addr) => {
+                    upstream_tx.send_replace(Some(addr));
+                    println!("wenzetu dev: app ready");
+                    tokio::time::sleep(RELOAD_DELAY).await;
+                    // Apps without live reload answer `404`
+                    let _ = send_reload(addr, &token).await;
+                }
+                None => {
+                    eprintln!("wenzetu dev: app exited before listening, waiting for changes");
+                }
+            }
+        } else {
+            eprintln!("wenzetu dev: build failed, waiting for changes");
+        }
+
+        if !next_change(&mut changes).await {
+            break;
+        }
+        println!("wenzetu dev: sources changed, rebuilding");
+    }
+    let _ = std::fs::remove_file(&addr_file);
+
+    if let Some(mut child) = child {
+        stop(&mut child).await;
+    }
+    Ok(())
+}
+
+/// Watch the configured paths, sending a message per batch of changes
+fn watch_sources(
+    config: &DevConfig,
+) -> std::io::Result<(Debouncer<RecommendedWatcher>, mpsc::UnboundedReceiver<()>)> {
+    let paths: Vec<PathBuf> = config
+        .watch
+        .iter()
+        .filter_map(|path| PathBuf::from(path).canonicalize().ok())
+        .collect();
+    let mut modified = ModifiedTimes::scan(&paths);
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    let mut debouncer =
+        new_debouncer(
+            config.debounce,
+            move |result: DebounceEventResult| match result {
+                Ok(events) => {
+                    if is_edit(&mut modified, &events) {
+                        let _ = tx.send(());
+                    }
+                }
+                Err(err) => eprintln!("wenzetu dev: watcher error: {}", err),
+            },
+        )
+        .map_err(std::io::Error::other)?;
+
+    for path in &paths {
+        debouncer
+            .watcher()
+            .watch(path, RecursiveMode::Recursive)
+            .map_err(std::io::Error::other)?;
+    }
+
+    Ok((debouncer, rx))
+}
+
+/// Whether a batch of events holds a real edit. Cargo reads the sources
+/// while building, which some platforms report as events.
+fn is_edit(modified: &mut ModifiedTimes, events: &[DebouncedEvent]) -> bool {
+    // Every path is looked at, so later batches compare against its new time
+    events
+        .iter()
+        .filter(|event| modified.changed(&event.path))
+        .count()
+        > 0
+}
+
+/// Wait for the next batch of changes, collapsing those that piled up
+/// during the build; `false` once the watcher stopped
+async fn next_change(changes: &mut mpsc::UnboundedReceiver<()>) -> bool {
+    if changes.recv().await.is_none() {
+        return false;
+    }
+    while changes.try_recv().is_ok() {}
+    true
+}
+
+/// Run `cargo build`, returning whether it succeeded
+async fn build(cargo_args: &[String]) -> std::io::Result<bool> {
+    let status = Command::new("cargo")
+        .arg("build")
+        .args(cargo_args)
+        .status()
+        .await?;
+    Ok(status.success())
+}
+
+/// Start the app on a port it picks, written to `addr_file` once bound
+async fn start(cargo_args: &[String], addr_file: &Path, token: &str) -> std::io::Result<Child> {
+    // An address left by the previous process must not be mistaken for
+    // the new one
+    match std::fs::remove_file(addr_file) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+
+    Command::new("cargo")
+        .args(["run", "--quiet"])
+        .args(cargo_args)
+        .env("APP.ADDRESS", "127.0.0.1")
+        .env("APP.PORT", "0")
+        .env(DEV_ADDR_FILE, addr_file)
+        .env(DEV_TOKEN, token)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+}
+
+/// Ask the app to shut down gracefully, killing it after [`STOP_TIMEOUT`]
+async fn stop(child: &mut Child) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id().and_then(|pid| libc::pid_t::try_from(pid).ok()) {
+        // SAFETY: `kill` only sends a signal, the child is not reaped yet
+        // since `id` returned its pid
+        unsafe { libc::kill(pid, libc::SIGTERM) };
+        if tokio::time::timeout(STOP_TIMEOUT, child.wait())
+            .await
+            .is_ok()
+        {
+            return;
+        }
+        eprintln!("wenzetu dev: app did not stop in time, killing it");
+    }
+    let _ = child.kill().await;
+}
+
+/// Wait until the app reported its address and accepts connections on it,
+/// or exits
+async fn wait_ready(child: &mut Child, addr_file: &Path) -> Option<SocketAddr> {
+    loop {
+        let addr = std::fs::read_to_string(addr_file)
+            .ok()
+            .and_then(|addr| addr.trim().parse().ok());
+        if let Some(addr) = addr
+            && TcpStream::connect(addr).await.is_ok()
+        {
+            return Some(addr);
+        }
+        if let Ok(Some(_)) = child.try_wait() {
+            return None;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// Ask the app to send a live reload event
+async fn send_reload(addr: SocketAddr, token: &str) -> std::io::Result<()> {
+    let mut stream = TcpStream::connect(addr).await?;
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\n{}: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        DEV_RELOAD_PATH, addr, DEV_TOKEN_HEADER, token
+    );
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    Ok(())
+}
+
+/// Forward every public connection to the current app process
+async fn accept(listener: TcpListener, upstream: watch::Receiver<Option<SocketAddr>>) {
+    loop {
+        let Ok((inbound, _)) = listener.accept().await else {
+            continue;
+        };
+        let upstream = upstream.clone();
+        tokio::spawn(async move {
+            if let Err(err) = forward(inbound, upstream).await {
+                eprintln!("wenzetu dev: proxy error: {}", err);
+            }
+        });
+    }
+}
+
+async fn forward(
+    mut inbound: TcpStream,
+    mut upstream: watch::Receiver<Option<SocketAddr>>,
+) -> std::io::Result<()> {
+    let addr = tokio::time::timeout(UPSTREAM_TIMEOUT, async {
+        loop {
+            if let Some(addr) = *upstream.borrow_and_update() {
+                return Some(addr);
+            }
+            if upstream.changed().await.is_err() {
+                return None;
+            }
+        }
+    })
+    .await
+    .ok()
+    .flatten()
+    .ok_or_else(|| std::io::Error::other("app did not come up"))?;
+
+    let mut outbound = TcpStream::connect(addr).await?;
+    tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::time::SystemTime;
+
+    use notify_debouncer_mini::DebouncedEventKind;
+
+    use super::*;
+
+    fn events(paths: &[&Path]) -> Vec<DebouncedEvent> {
+        paths
+            .iter()
+            .map(|path| DebouncedEvent::new(path.to_path_buf(), DebouncedEventKind::Any))
+            .collect()
+    }
+
+    fn touch(path: &Path, seconds: u64) {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+    }
+
+    #[test]
+    fn only_edits_trigger_a_rebuild() {
+        let dir = tempfile::tempdir().unwrap();
+        let (main, lib) = (dir.path().join("main.rs"), dir.path().join("lib.rs"));
+        for path in [&main, &lib] {
+            std::fs::write(path, "").unwrap();
+            touch(path, 1);
+        }
+        let mut modified = ModifiedTimes::scan(&[dir.path().to_path_buf()]);
+
+        // Cargo reading the sources
+        assert!(!is_edit(&mut modified, &events(&[&main, &lib])));
+
+        touch(&main, 2);
+        touch(&lib, 2);
+        assert!(is_edit(&mut modified, &events(&[&main, &lib])));
+        // Both edits were seen, reading them again is no change
+        assert!(!is_edit(&mut modified, &events(&[&lib])));
+    }
+
+    #[tokio::test]
+    async fn changes_during_a_build_collapse_into_one_rebuild() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        for _ in 0..3 {
+            tx.send(()).unwrap();
+        }
+
+        assert!(next_change(&mut rx).await);
+        assert!(rx.try_recv().is_err());
+
+        drop(tx);
+        assert!(!next_change(&mut rx).await);
+    }
+
+    #[tokio::test]
+    async fn waits_for_the_reported_address() {
+        let dir = tempfile::tempdir().unwrap();
+        let addr_file = dir.path().join("app.addr");
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut child = Command::new("sleep")
+            .arg("10")
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+
+        let report = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            std::fs::write(&addr_file, addr.to_string()).unwrap();
+        };
+        let (ready, _) = tokio::join!(wait_ready(&mut child, &addr_file), report);
+        assert_eq!(ready, Some(addr));
+    }
+
+    #[tokio::test]
+    async fn exited_apps_are_not_waited_for() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut child = Command::new("true").spawn().unwrap();
+
+        let ready = wait_ready(&mut child, &dir.path().join("app.addr")).await;
+        assert_eq!(ready, None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn stop_terminates_gracefully() {
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+
+        stop(&mut child).await;
+        let status = child.wait().await.unwrap();
+        // Killed by `SIGTERM`, not `SIGKILL`
+        assert_eq!(
+            std::os::unix::process::ExitStatusExt::signal(&status),
+            Some(libc::SIGTERM)
+        );
+    }
+}
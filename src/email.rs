@@ -0,0 +1,257 @@
+//! Email template rendering
+//!
+//! Renders the HTML and plain-text parts of an email from sibling templates
+//! (`name.html` and `name.txt`) sharing one context, and hands the result to
+//! an [`EmailSink`].
+
+use std::sync::{Arc, Mutex};
+
+use tera::Context;
+
+use crate::templates;
+
+/// A rendered email, ready to hand to an SMTP library
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Email {
+    /// Subject line, from the optional `name.subject.txt` template
+    pub subject: Option<String>,
+    /// HTML part with CSS inlined into `style` attributes
+    pub html: String,
+    /// Plain-text part
+    pub text: String,
+}
+
+/// An email template made of `name.html`, `name.txt` and an optional
+/// `name.subject.txt`.
+///
+/// # Example
+/// ```rust,no_run
+/// use wenzetu::{context, email::EmailTemplate};
+///
+/// let email = EmailTemplate::new("emails/welcome")
+///     .render(&context! { name: "Ada" })
+///     .unwrap();
+///
+/// // e.g. with lettre:
+/// // Message::builder()
+/// //     .subject(email.subject.unwrap_or_default())
+/// //     .multipart(MultiPart::alternative_plain_html(email.text, email.html))
+/// ```
+#[derive(Debug, Clone)]
+pub struct EmailTemplate {
+    name: String,
+    inline_css: bool,
+}
+
+impl EmailTemplate {
+    /// Create an email template from its name without extension
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            inline_css: true,
+        }
+    }
+
+    /// Enable or disable CSS inlining for the HTML part (enabled by default)
+    pub fn inline_css(mut self, enabled: bool) -> Self {
+        self.inline_css = enabled;
+        self
+    }
+
+    /// Name of the HTML template
+    pub fn html_template(&self) -> String {
+        format!("{}.html", self.name)
+    }
+
+    /// Name of the plain-text template
+    pub fn text_template(&self) -> String {
+        format!("{}.txt", self.name)
+    }
+
+    /// Name of the optional subject template
+    pub fn subject_template(&self) -> String {
+        format!("{}.subject.txt", self.name)
+    }
+
+    /// Render both parts, and the subject if its template exists
+    pub fn render(&self, context: &Context) -> Result<Email, String> {
+        let html = templates::try_render(&self.html_template(), context)?;
+        let text = templates::try_render(&self.text_template(), context)?;
+
+        let subject_template = self.subject_template();
+        let has_subject = templates::TEMPLATES
+            .read()
+            .map(|templates| templates.has_template(&subject_template))
+            .unwrap_or(false);
+        let subject = if has_subject {
+            let subject = templates::try_render(&subject_template, context)?;
+            Some(
+                subject
+                    .lines()
+                    .map(str::trim)
+                    .collect::<Vec<_>>()
+                    .join(" ")
+                    .trim()
+                    .to_string(),
+            )
+        } else {
+            None
+        };
+
+        let html = if self.inline_css {
+            inline_css(&html)?
+        } else {
+            html
+        };
+
+        Ok(Email {
+            subject,
+            html,
+            text,
+        })
+    }
+
+    /// Render the email and deliver it to `to` through `sink`
+    pub fn send(&self, sink: &impl EmailSink, to: &str, context: &Context) -> Result<(), String> {
+        let email = self.render(context)?;
+        sink.send(to, &email)
+    }
+}
+
+/// Where rendered emails are delivered, e.g. an adapter around an SMTP
+/// transport
+pub trait EmailSink {
+    /// Deliver `email` to the address `to`
+    fn send(&self, to: &str, email: &Email) -> Result<(), String>;
+}
+
+/// Sink keeping emails in memory instead of sending them, for tests and
+/// local development.
+///
+/// Clones share the captured emails.
+///
+/// # Example
+/// ```rust,no_run
+/// use wenzetu::{context, email::{CaptureSink, EmailTemplate}};
+///
+/// let sink = CaptureSink::new();
+/// EmailTemplate::new("emails/welcome")
+///     .send(&sink, "ada@example.com", &context! { name: "Ada" })
+///     .unwrap();
+///
+/// let (to, email) = &sink.sent()[0];
+/// assert_eq!(to, "ada@example.com");
+/// assert!(email.text.contains("Ada"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CaptureSink {
+    sent: Arc<Mutex<Vec<(String, Email)>>>,
+}
+
+impl CaptureSink {
+    /// Create an empty sink
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Emails sent so far with their recipient, oldest first
+    pub fn sent(&self) -> Vec<(String, Email)> {
+        self.sent
+            .lock()
+            .map(|sent| sent.clone())
+            .unwrap_or_default()
+    }
+
+    /// Forget the emails sent so far
+    pub fn clear(&self) {
+        if let Ok(mut sent) = self.sent.lock() {
+            sent.clear();
+        }
+    }
+}
+
+impl EmailSink for CaptureSink {
+    fn send(&self, to: &str, email: &Email) -> Result<(), String> {
+        let mut sent = self.sent.lock().map_err(|err| err.to_string())?;
+        sent.push((to.to_string(), email.clone()));
+        Ok(())
+    }
+}
+
+/// Move `<style>` rules into `style` attributes, since many mail clients
+/// ignore stylesheets
+pub fn inline_css(html: &str) -> Result<String, String> {
+    css_inline::CSSInliner::options()
+        .load_remote_stylesheets(false)
+        .build()
+        .inline(html)
+        .map_err(|err| format!("failed to inline CSS: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context;
+    use crate::templates::load_test_templates;
+
+    #[test]
+    fn renders_both_parts_and_the_subject() {
+        load_test_templates();
+
+        let email = EmailTemplate::new("emails/welcome")
+            .render(&context! { name: "Ada" })
+            .unwrap();
+
+        assert_eq!(email.subject.as_deref(), Some("Welcome, Ada!"));
+        assert!(email.text.contains("Hello Ada,"));
+        assert!(email.html.contains("Hello Ada"));
+        // The stylesheet is inlined into the elements it applies to
+        assert!(email.html.contains("style=\"color: #333"));
+        assert!(!email.html.contains("<style>"));
+    }
+
+    #[test]
+    fn subject_is_optional() {
+        load_test_templates();
+
+        let email = EmailTemplate::new("emails/receipt")
+            .inline_css(false)
+            .render(&context! { total: "9.99" })
+            .unwrap();
+
+        assert_eq!(email.subject, None);
+        assert!(email.text.contains("9.99"));
+    }
+
+    #[test]
+    fn missing_part_is_an_error() {
+        load_test_templates();
+
+        let result = EmailTemplate::new("emails/missing").render(&context! {});
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn capture_sink_records_sent_emails() {
+        load_test_templates();
+        let sink = CaptureSink::new();
+
+        let template = EmailTemplate::new("emails/welcome");
+        template
+            .send(&sink, "ada@example.com", &context! { name: "Ada" })
+            .unwrap();
+        template
+            .send(&sink.clone(), "bob@example.com", &context! { name: "Bob" })
+            .unwrap();
+
+        let sent = sink.sent();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].0, "ada@example.com");
+        assert_eq!(sent[0].1.subject.as_deref(), Some("Welcome, Ada!"));
+        assert_eq!(sent[1].0, "bob@example.com");
+        assert!(sent[1].1.text.contains("Hello Bob,"));
+
+        sink.clear();
+        assert!(sink.sent().is_empty());
+    }
+}
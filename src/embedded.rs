@@ -0,0 +1,494 @@
+//! Static files compiled into the binary
+//!
+//! Bundles a directory at compile time with [`embed_dir!`](crate::embed_dir)
+//! and serves it from memory, so deployments don't need to ship `./public`.
+//! Files get MIME types, ETags, `Last-Modified`, cache rules and byte range
+//! support like files served from disk.
+//!
+//! ```rust,ignore
+//! use wenzetu::app::StaticConfig;
+//!
+//! wenzetu::embed_dir!(Public, "public");
+//!
+//! App::new()
+//!     .static_config(StaticConfig::new("/public", "./public").embedded::<Public>())
+//! ```
+//!
+//! In `Environment::Development` the directory is served from disk when it
+//! exists, so edits show up without a rebuild.
+
+use std::borrow::Cow;
+use std::convert::Infallible;
+use std::future::{Ready, ready};
+use std::path::Path;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use http::header::{
+    ACCEPT_ENCODING, ACCEPT_RANGES, ALLOW, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH,
+    CONTENT_RANGE, CONTENT_TYPE, ETAG, HeaderMap, HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+    IF_RANGE, LAST_MODIFIED, RANGE, VARY,
+};
+use http::{Method, Request, Response, StatusCode};
+use http_body_util::Full;
+use tower_service::Service;
+
+use crate::app::{StaticConfig, StaticOptions};
+use crate::static_files::{CachePolicy, IMMUTABLE, SpaFallback, etag_matches, request_path};
+
+pub use rust_embed;
+pub use rust_embed::{EmbeddedFile, RustEmbed};
+
+/// Declare a type embedding a directory, relative to the crate root.
+///
+/// Debug builds read the files from disk on each request, release builds
+/// include them in the binary.
+///
+/// # Example
+/// ```rust,ignore
+/// wenzetu::embed_dir!(pub Public, "public");
+///
+/// let files = wenzetu::embedded::EmbeddedFiles::of::<Public>();
+/// ```
+#[macro_export]
+macro_rules! embed_dir {
+    ($vis:vis $name:ident, $folder:tt) => {
+        #[derive($crate::embedded::RustEmbed)]
+        #[folder = $folder]
+        #[crate_path = "wenzetu::embedded::rust_embed"]
+        $vis struct $name;
+    };
+}
+
+/// Files of a [`RustEmbed`] type
+#[derive(Debug, Clone, Copy)]
+pub struct EmbeddedFiles {
+    get: fn(&str) -> Option<EmbeddedFile>,
+    names: fn() -> Box<dyn Iterator<Item = Cow<'static, str>>>,
+}
+
+impl EmbeddedFiles {
+    /// Files embedded by `E`
+    pub fn of<E: RustEmbed>() -> Self {
+        Self {
+            get: E::get,
+            names: || Box::new(E::iter()),
+        }
+    }
+
+    /// File at a path relative to the embedded directory
+    pub fn get(&self, path: &str) -> Option<EmbeddedFile> {
+        (self.get)(path)
+    }
+
+    /// Paths of all files, relative to the embedded directory
+    pub fn names(&self) -> impl Iterator<Item = Cow<'static, str>> {
+        (self.names)()
+    }
+}
+
+/// Static file service serving [`EmbeddedFiles`] from memory
+#[derive(Clone)]
+pub struct EmbeddedService {
+    files: EmbeddedFiles,
+    serve_path: String,
+    precompressed: bool,
+    cache: CachePolicy,
+    spa: Option<SpaFallback>,
+    options: StaticOptions,
+    etag: bool,
+    last_modified: bool,
+}
+
+impl EmbeddedService {
+    /// Create a service serving `files` as configured by `config`
+    pub fn new(config: &StaticConfig, files: EmbeddedFiles) -> Self {
+        Self {
+            files,
+            serve_path: config.serve_path.clone(),
+            precompressed: config.precompressed,
+            cache: CachePolicy::new(config),
+            spa: SpaFallback::new(config),
+            options: config.options.clone(),
+            etag: config.etag,
+            last_modified: config.last_modified,
+        }
+    }
+
+    fn respond(&self, headers: &HeaderMap, method: &Method, path: &str) -> Response<Full<Bytes>> {
+        if method != Method::GET && method != Method::HEAD {
+            let mut response = empty_response(StatusCode::METHOD_NOT_ALLOWED);
+            response
+                .headers_mut()
+                .insert(ALLOW, HeaderValue::from_static("GET,HEAD"));
+            return response;
+        }
+
+        let requested = request_path(path).to_string_lossy().replace('\\', "/");
+        let original = crate::assets::original_path(&self.serve_path, &requested);
+        let name = original.clone().unwrap_or(requested);
+        let index = match name.as_str() {
+            "" => "index.html".to_string(),
+            name => format!("{}/index.html", name),
+        };
+        let spa_index = self
+            .spa
+            .as_ref()
+            .filter(|spa| original.is_none() && spa.applies(Path::new(&name)))
+            .map(|_| "index.html".to_string());
+        let Some((name, file)) = [name, index]
+            .into_iter()
+            .chain(spa_index)
+            .find_map(|name| self.files.get(&name).map(|file| (name, file)))
+        else {
+            return empty_response(StatusCode::NOT_FOUND);
+        };
+        if !self.options.allows(Path::new(&name)) {
+            return empty_response(StatusCode::NOT_FOUND);
+        }
+        // Names from the manifest change with the content
+        let cache_control = if original.is_some() {
+            Some(HeaderValue::from_static(IMMUTABLE))
+        } else {
+            self.cache.get(Path::new(&name))
+        };
+
+        let mut response_headers = HeaderMap::new();
+        if let Ok(value) = HeaderValue::from_str(file.metadata.mimetype()) {
+            response_headers.insert(CONTENT_TYPE, value);
+        }
+        if let Some(value) = cache_control {
+            response_headers.insert(CACHE_CONTROL, value);
+        }
+        let modified = file
+            .metadata
+            .last_modified()
+            .filter(|_| self.last_modified)
+            .map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds));
+        if let Some(modified) = modified
+            && let Ok(value) = HeaderValue::from_str(&httpdate::fmt_http_date(modified))
+        {
+            response_headers.insert(LAST_MODIFIED, value);
+        }
+
+        // Each encoding is a different representation with its own ETag
+        let mut file = file;
+        if self.precompressed {
+            response_headers.insert(VARY, HeaderValue::from_static("accept-encoding"));
+            for (encoding, extension) in [("br", "br"), ("gzip", "gz")] {
+                if accepts_encoding(headers, encoding)
+                    && let Some(sibling) = self.files.get(&format!("{}.{}", name, extension))
+                {
+                    file = sibling;
+                    response_headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding));
+                    break;
+                }
+            }
+        }
+        let etag = self.etag.then(|| file_etag(&file));
+        if let Some(etag) = &etag {
+            response_headers.insert(ETAG, etag.clone());
+        }
+
+        // `If-None-Match` takes precedence over `If-Modified-Since`
+        let if_none_match = headers.get(IF_NONE_MATCH).filter(|_| self.etag);
+        let fresh = match (if_none_match, &etag) {
+            (Some(value), Some(etag)) => etag_matches(value, etag),
+            _ => if_none_match.is_none() && not_modified_since(headers, modified),
+        };
+        if fresh {
+            let mut response = empty_response(StatusCode::NOT_MODIFIED);
+            response_headers.remove(CONTENT_TYPE);
+            response_headers.remove(CONTENT_ENCODING);
+            response.headers_mut().extend(response_headers);
+            return response;
+        }
+
+        let data = match file.data {
+            Cow::Borrowed(data) => Bytes::from_static(data),
+            Cow::Owned(data) => Bytes::from(data),
+        };
+        let len = data.len() as u64;
+        response_headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+        let range = headers
+            .get(RANGE)
+            .filter(|_| if_range_matches(headers, etag.as_ref(), modified))
+            .map_or(ByteRange::Full, |value| byte_range(value, len));
+        let (status, body) = match range {
+            ByteRange::Full => (StatusCode::OK, data),
+            ByteRange::Partial(start, end) => {
+                let content_range = format!("bytes {}-{}/{}", start, end, len);
+                if let Ok(value) = HeaderValue::from_str(&content_range) {
+                    response_headers.insert(CONTENT_RANGE, value);
+                }
+                let body = data.slice(start as usize..=end as usize);
+                (StatusCode::PARTIAL_CONTENT, body)
+            }
+            ByteRange::Unsatisfiable => {
+                let mut response = empty_response(StatusCode::RANGE_NOT_SATISFIABLE);
+                if let Ok(value) = HeaderValue::from_str(&format!("bytes */{}", len)) {
+                    response.headers_mut().insert(CONTENT_RANGE, value);
+                }
+                return response;
+            }
+        };
+
+        response_headers.insert(CONTENT_LENGTH, HeaderValue::from(body.len()));
+        let body = if method == Method::HEAD {
+            Bytes::new()
+        } else {
+            body
+        };
+        let mut response = Response::new(Full::new(body));
+        *response.status_mut() = status;
+        *response.headers_mut() = response_headers;
+        response
+    }
+}
+
+impl<B> Service<Request<B>> for EmbeddedService {
+    type Response = Response<Full<Bytes>>;
+    type Error = Infallible;
+    type Future = Ready<Result<Self::Response, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        ready(Ok(self.respond(
+            req.headers(),
+            req.method(),
+            req.uri().path(),
+        )))
+    }
+}
+
+/// Byte range selected by a `Range` header
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    /// No usable range, the whole file is sent
+    Full,
+    /// First and last byte, inclusive
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/// Parse a single `bytes=` range. Multiple ranges and invalid headers are
+/// ignored, which serves the whole file as allowed by RFC 9110.
+fn byte_range(header: &HeaderValue, len: u64) -> ByteRange {
+    let Some(spec) = header
+        .to_str()
+        .ok()
+        .and_then(|value| value.trim().strip_prefix("bytes="))
+    else {
+        return ByteRange::Full;
+    };
+    let Some((start, end)) = spec.split_once('-').filter(|_| !spec.contains(',')) else {
+        return ByteRange::Full;
+    };
+    let (start, end) = (start.trim(), end.trim());
+
+    if start.is_empty() {
+        // `bytes=-500` is the last 500 bytes
+        return match end.parse::<u64>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(_) if len == 0 => ByteRange::Unsatisfiable,
+            Ok(suffix) => ByteRange::Partial(len.saturating_sub(suffix), len - 1),
+            Err(_) => ByteRange::Full,
+        };
+    }
+
+    let Ok(start) = start.parse::<u64>() else {
+        return ByteRange::Full;
+    };
+    let end = if end.is_empty() {
+        u64::MAX
+    } else {
+        match end.parse::<u64>() {
+            Ok(end) if end >= start => end,
+            _ => return ByteRange::Full,
+        }
+    };
+    if start >= len {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Partial(start, end.min(len - 1))
+}
+
+/// Whether a `Range` applies under `If-Range`, which only allows it while
+/// the representation is unchanged
+fn if_range_matches(
+    headers: &HeaderMap,
+    etag: Option<&HeaderValue>,
+    modified: Option<SystemTime>,
+) -> bool {
+    let Some(value) = headers.get(IF_RANGE) else {
+        return true;
+    };
+    if etag.is_some_and(|etag| etag == value) {
+        return true;
+    }
+    let date = value
+        .to_str()
+        .ok()
+        .and_then(|value| httpdate::parse_http_date(value).ok());
+    date.zip(modified)
+        .is_some_and(|(date, modified)| date == modified)
+}
+
+/// Whether `If-Modified-Since` is at or after the file's modification time
+fn not_modified_since(headers: &HeaderMap, modified: Option<SystemTime>) -> bool {
+    let since = headers
+        .get(IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| httpdate::parse_http_date(value).ok());
+    since
+        .zip(modified)
+        .is_some_and(|(since, modified)| modified <= since)
+}
+
+/// Whether `Accept-Encoding` allows `encoding`, i.e. lists it without `q=0`
+fn accepts_encoding(headers: &HeaderMap, encoding: &str) -> bool {
+    headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|candidate| {
+            let mut parts = candidate.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let rejected = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            name.eq_ignore_ascii_case(encoding) && !rejected
+        })
+}
+
+/// Strong ETag from the hash computed when the file was embedded, in the
+/// same format as files served from disk
+fn file_etag(file: &EmbeddedFile) -> HeaderValue {
+    let hash: String = file.metadata.sha256_hash()[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    HeaderValue::from_str(&format!("\"{}\"", hash)).expect("hex is a valid header value")
+}
+
+fn empty_response(status: StatusCode) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::new()));
+    *response.status_mut() = status;
+    crate::error_pages::mark(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(RustEmbed)]
+    #[folder = "tests/embedded"]
+    struct Fixtures;
+
+    const APP_JS: &[u8] = b"console.log('embedded');\n";
+
+    fn service(config: StaticConfig) -> EmbeddedService {
+        EmbeddedService::new(&config, EmbeddedFiles::of::<Fixtures>())
+    }
+
+    fn get(
+        service: &EmbeddedService,
+        headers: &[(http::HeaderName, &str)],
+    ) -> Response<Full<Bytes>> {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(name, HeaderValue::from_str(value).unwrap());
+        }
+        service.respond(&map, &Method::GET, "/app.js")
+    }
+
+    fn range(value: &'static str, len: u64) -> ByteRange {
+        byte_range(&HeaderValue::from_static(value), len)
+    }
+
+    #[test]
+    fn parses_byte_ranges() {
+        assert_eq!(range("bytes=0-", 10), ByteRange::Partial(0, 9));
+        assert_eq!(range("bytes=2-4", 10), ByteRange::Partial(2, 4));
+        assert_eq!(range("bytes=5-100", 10), ByteRange::Partial(5, 9));
+        assert_eq!(range("bytes=-3", 10), ByteRange::Partial(7, 9));
+        assert_eq!(range("bytes=-30", 10), ByteRange::Partial(0, 9));
+
+        assert_eq!(range("bytes=10-", 10), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=-0", 10), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=-1", 0), ByteRange::Unsatisfiable);
+
+        // Invalid and multiple ranges serve the whole file
+        for value in ["bytes=4-2", "bytes=a-", "bytes=0-1,3-4", "items=0-1"] {
+            assert_eq!(range(value, 10), ByteRange::Full, "{}", value);
+        }
+    }
+
+    #[test]
+    fn serves_ranges() {
+        let service = service(StaticConfig::new("/public", "./missing").precompressed(false));
+        let len = APP_JS.len();
+
+        let response = get(&service, &[(RANGE, "bytes=-3")]);
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        let expected = format!("bytes {}-{}/{}", len - 3, len - 1, len);
+        assert_eq!(response.headers()[CONTENT_RANGE], expected.as_str());
+
+        let response = get(&service, &[(RANGE, "bytes=1000-")]);
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        let expected = format!("bytes */{}", len);
+        assert_eq!(response.headers()[CONTENT_RANGE], expected.as_str());
+    }
+
+    #[test]
+    fn if_range_only_applies_to_the_same_representation() {
+        let service = service(StaticConfig::new("/public", "./missing").precompressed(false));
+        let etag = get(&service, &[]).headers()[ETAG].clone();
+
+        let response = get(
+            &service,
+            &[(RANGE, "bytes=0-"), (IF_RANGE, etag.to_str().unwrap())],
+        );
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+
+        let response = get(&service, &[(RANGE, "bytes=0-"), (IF_RANGE, "\"outdated\"")]);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key(CONTENT_RANGE));
+    }
+
+    #[test]
+    fn negotiates_precompressed_siblings() {
+        let precompressed = service(StaticConfig::new("/public", "./missing"));
+        let encoding = |accept| {
+            let response = get(&precompressed, &[(ACCEPT_ENCODING, accept)]);
+            assert_eq!(response.headers()[VARY], "accept-encoding");
+            response
+                .headers()
+                .get(CONTENT_ENCODING)
+                .map(|value| value.to_str().unwrap().to_string())
+        };
+
+        assert_eq!(encoding("gzip, br").as_deref(), Some("br"));
+        assert_eq!(encoding("br;q=0, gzip").as_deref(), Some("gzip"));
+        assert_eq!(encoding("GZIP").as_deref(), Some("gzip"));
+        assert_eq!(encoding("identity"), None);
+
+        // Each encoding has its own ETag
+        let br = get(&precompressed, &[(ACCEPT_ENCODING, "br")]);
+        let identity = get(&precompressed, &[]);
+        assert_ne!(br.headers()[ETAG], identity.headers()[ETAG]);
+
+        let plain = service(StaticConfig::new("/public", "./missing").precompressed(false));
+        let response = get(&plain, &[(ACCEPT_ENCODING, "br")]);
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+    }
+}
@@ -0,0 +1,443 @@
+//! Template engine backends
+//!
+//! Tera is always available and used by default. MiniJinja and Handlebars
+//! can be enabled with the `minijinja` and `handlebars` features.
+
+use std::any::Any;
+use std::sync::{Arc, RwLock};
+
+use tera::{Context, Tera};
+
+use crate::error::BoxError;
+
+#[cfg(any(feature = "minijinja", feature = "handlebars"))]
+use std::path::Component;
+#[cfg(any(
+    feature = "live-reload",
+    feature = "minijinja",
+    feature = "handlebars",
+    test
+))]
+use std::path::{Path, PathBuf};
+
+/// Extensions rendered with HTML auto-escaping
+const AUTOESCAPE_EXTENSIONS: [&str; 4] = [".html", ".htm", ".xml", ".svg"];
+
+/// Customization of a Tera instance, see [`crate::templates::configure_tera`]
+pub(crate) type TeraSetup = Arc<dyn Fn(&mut Tera) + Send + Sync>;
+
+/// Customizations applied to every Tera instance after loading templates
+pub(crate) static TERA_SETUP: RwLock<Vec<TeraSetup>> = RwLock::new(Vec::new());
+
+/// A template engine that can render templates loaded from a glob path.
+///
+/// Render errors are reported as strings so they can be shown on the error
+/// pages regardless of the backend. Load errors keep the backend's error so
+/// [`Error::Template`](crate::Error::Template) can report the cause.
+pub trait TemplateEngine: Send + Sync {
+    /// Render a template with the given context
+    fn render(&self, name: &str, context: &Context) -> Result<String, String>;
+
+    /// Reload all templates from disk.
+    ///
+    /// On error the previously loaded templates are kept.
+    fn reload(&mut self) -> Result<(), BoxError>;
+
+    /// Check whether a template with the given name is loaded
+    fn has_template(&self, name: &str) -> bool;
+
+    /// The engine as [`Any`], to reach the backend behind
+    /// `templates::TEMPLATES`, e.g. a [`TeraEngine`]
+    fn as_any_mut(&mut self) -> Option<&mut dyn Any> {
+        None
+    }
+}
+
+/// Available template engine backends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Engine {
+    /// Tera (default)
+    #[default]
+    Tera,
+    /// MiniJinja
+    #[cfg(feature = "minijinja")]
+    MiniJinja,
+    /// Handlebars
+    #[cfg(feature = "handlebars")]
+    Handlebars,
+}
+
+impl Engine {
+    /// Create an engine for templates matching `path`, without loading them
+    pub fn create(self, path: &str) -> Box<dyn TemplateEngine> {
+        match self {
+            Engine::Tera => Box::new(TeraEngine::new(path)),
+            #[cfg(feature = "minijinja")]
+            Engine::MiniJinja => Box::new(MiniJinjaEngine::new(path)),
+            #[cfg(feature = "handlebars")]
+            Engine::Handlebars => Box::new(HandlebarsEngine::new(path)),
+        }
+    }
+}
+
+/// Tera backend
+pub struct TeraEngine {
+    path: String,
+    tera: Tera,
+}
+
+impl TeraEngine {
+    /// Create an engine for templates matching `path`
+    pub fn new(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            tera: Tera::default(),
+        }
+    }
+
+    /// Access the underlying Tera instance
+    pub fn tera(&self) -> &Tera {
+        &self.tera
+    }
+
+    /// Mutable access to the underlying Tera instance. Changes are lost on
+    /// reload, use [`crate::templates::configure_tera`] to keep them.
+    pub fn tera_mut(&mut self) -> &mut Tera {
+        &mut self.tera
+    }
+}
+
+impl TemplateEngine for TeraEngine {
+    fn render(&self, name: &str, context: &Context) -> Result<String, String> {
+        self.tera.render(name, context).map_err(tera_error)
+    }
+
+    fn reload(&mut self) -> Result<(), BoxError> {
+        let mut tera = Tera::new(&self.path)?;
+
+        // Configure auto-escaping for security
+        tera.autoescape_on(AUTOESCAPE_EXTENSIONS.to_vec());
+        tera.register_function("asset", crate::assets::AssetFunction);
+
+        #[cfg(feature = "markdown")]
+        tera.register_filter("markdown", crate::markdown::MarkdownFilter);
+
+        // Registered after the built-ins so they can be replaced
+        if let Ok(setups) = TERA_SETUP.read() {
+            for setup in setups.iter() {
+                setup(&mut tera);
+            }
+        }
+
+        self.tera = tera;
+        Ok(())
+    }
+
+    fn has_template(&self, name: &str) -> bool {
+        self.tera.get_template_names().any(|n| n == name)
+    }
+
+    fn as_any_mut(&mut self) -> Option<&mut dyn Any> {
+        Some(self)
+    }
+}
+
+/// Format a Tera error with its causes, which hold the parse location and
+/// the actual render failure
+fn tera_error(err: tera::Error) -> String {
+    error_message(&err)
+}
+
+/// Format an error with its causes, one per line
+pub(crate) fn error_message(err: &(dyn std::error::Error + 'static)) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+    while let Some(cause) = source {
+        message.push('\n');
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+/// Find the template file and line in an engine error message
+pub(crate) fn error_location(message: &str) -> (Option<String>, Option<u32>) {
+    let after = |pattern: &str| {
+        message
+            .find(pattern)
+            .map(|index| &message[index + pattern.len()..])
+    };
+
+    // MiniJinja: `... (in index.html:3)`
+    if let Some(rest) = after("(in ")
+        && let Some((location, _)) = rest.split_once(')')
+        && let Some((file, line)) = location.rsplit_once(':')
+    {
+        return (Some(file.to_string()), line.parse().ok());
+    }
+
+    // Handlebars: `--> Template error in "index.hbs":3:5`
+    if let Some(rest) = after("error in \"")
+        && let Some((file, rest)) = rest.split_once('"')
+    {
+        let line = rest
+            .strip_prefix(':')
+            .and_then(|rest| rest.split(':').next())
+            .and_then(|line| line.parse().ok());
+        return (Some(file.to_string()), line);
+    }
+
+    // Tera: `Failed to parse 'index.html'` and later ` --> 3:5`
+    let file = after("Failed to parse ").and_then(|rest| {
+        let quote = rest.chars().next().filter(|c| matches!(c, '\'' | '"'))?;
+        let (file, _) = rest[1..].split_once(quote)?;
+        Some(file.to_string())
+    });
+    let line = after("--> ").and_then(|rest| rest.split(':').next()?.trim().parse().ok());
+    (file, line)
+}
+
+/// MiniJinja backend
+#[cfg(feature = "minijinja")]
+pub struct MiniJinjaEngine {
+    path: String,
+    env: minijinja::Environment<'static>,
+}
+
+#[cfg(feature = "minijinja")]
+impl MiniJinjaEngine {
+    /// Create an engine for templates matching `path`
+    pub fn new(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            env: Self::new_environment(),
+        }
+    }
+
+    /// Access the underlying MiniJinja environment
+    pub fn environment(&self) -> &minijinja::Environment<'static> {
+        &self.env
+    }
+
+    fn new_environment() -> minijinja::Environment<'static> {
+        let mut env = minijinja::Environment::new();
+        env.set_auto_escape_callback(|name| {
+            if AUTOESCAPE_EXTENSIONS.iter().any(|ext| name.ends_with(ext)) {
+                minijinja::AutoEscape::Html
+            } else {
+                minijinja::AutoEscape::None
+            }
+        });
+        env.add_function("asset", |path: &str| {
+            minijinja::Value::from_safe_string(crate::assets::asset_url(path))
+        });
+        env
+    }
+}
+
+#[cfg(feature = "minijinja")]
+impl TemplateEngine for MiniJinjaEngine {
+    fn render(&self, name: &str, context: &Context) -> Result<String, String> {
+        let template = self.env.get_template(name).map_err(|err| err.to_string())?;
+        template
+            .render(context.clone().into_json())
+            .map_err(|err| err.to_string())
+    }
+
+    fn reload(&mut self) -> Result<(), BoxError> {
+        let mut env = Self::new_environment();
+        for (name, source) in load_sources(&self.path)? {
+            env.add_template_owned(name, source)?;
+        }
+        self.env = env;
+        Ok(())
+    }
+
+    fn has_template(&self, name: &str) -> bool {
+        self.env.get_template(name).is_ok()
+    }
+
+    fn as_any_mut(&mut self) -> Option<&mut dyn Any> {
+        Some(self)
+    }
+}
+
+/// Handlebars backend
+#[cfg(feature = "handlebars")]
+pub struct HandlebarsEngine {
+    path: String,
+    registry: handlebars::Handlebars<'static>,
+}
+
+#[cfg(feature = "handlebars")]
+impl HandlebarsEngine {
+    /// Create an engine for templates matching `path`
+    pub fn new(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            registry: handlebars::Handlebars::new(),
+        }
+    }
+
+    /// Access the underlying Handlebars registry
+    pub fn registry(&self) -> &handlebars::Handlebars<'static> {
+        &self.registry
+    }
+}
+
+#[cfg(feature = "handlebars")]
+impl TemplateEngine for HandlebarsEngine {
+    fn render(&self, name: &str, context: &Context) -> Result<String, String> {
+        self.registry
+            .render(name, &context.clone().into_json())
+            .map_err(|err| err.to_string())
+    }
+
+    fn reload(&mut self) -> Result<(), BoxError> {
+        let mut registry = handlebars::Handlebars::new();
+        registry.register_helper("asset", Box::new(asset_helper));
+        for (name, source) in load_sources(&self.path)? {
+            registry.register_template_string(&name, source)?;
+        }
+        self.registry = registry;
+        Ok(())
+    }
+
+    fn has_template(&self, name: &str) -> bool {
+        self.registry.has_template(name)
+    }
+
+    fn as_any_mut(&mut self) -> Option<&mut dyn Any> {
+        Some(self)
+    }
+}
+
+#[cfg(feature = "handlebars")]
+handlebars::handlebars_helper!(asset_helper: |path: str| crate::assets::asset_url(path));
+
+/// Read every file matching `pattern`, named by its path relative to the
+/// directory the glob starts from (the same naming Tera uses)
+#[cfg(any(feature = "minijinja", feature = "handlebars"))]
+fn load_sources(pattern: &str) -> Result<Vec<(String, String)>, String> {
+    let (base, _) = split_glob(pattern);
+    let base = without_cur_dir(&base);
+    let paths = glob::glob(pattern).map_err(|err| err.to_string())?;
+
+    let mut sources = Vec::new();
+    for path in paths {
+        let path = path.map_err(|err| err.to_string())?;
+        if !path.is_file() {
+            continue;
+        }
+
+        let path = without_cur_dir(&path);
+        let name = path
+            .strip_prefix(&base)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .replace('\\', "/");
+        let source = std::fs::read_to_string(&path)
+            .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+        sources.push((name, source));
+    }
+    Ok(sources)
+}
+
+/// Split a glob into the directory it starts from and the pattern for files
+/// below it, e.g. `./views/**/*.html` into `./views` and `**/*.html`
+#[cfg(any(
+    feature = "live-reload",
+    feature = "minijinja",
+    feature = "handlebars",
+    test
+))]
+pub(crate) fn split_glob(glob: &str) -> (PathBuf, String) {
+    let mut directory = PathBuf::new();
+    let mut pattern = Vec::new();
+    for component in Path::new(glob).components() {
+        let part = component.as_os_str().to_string_lossy();
+        if pattern.is_empty() && !part.contains(['*', '?', '[', '{']) {
+            directory.push(component);
+        } else {
+            pattern.push(part.into_owned());
+        }
+    }
+
+    if directory.as_os_str().is_empty() {
+        directory.push(".");
+    }
+    if pattern.is_empty() {
+        // A plain directory, everything below it
+        pattern.push("**/*".to_string());
+    }
+    (directory, pattern.join("/"))
+}
+
+/// Drop `.` components so `./templates` and `templates` compare equal
+#[cfg(any(feature = "minijinja", feature = "handlebars"))]
+fn without_cur_dir(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_globs_at_the_first_pattern() {
+        assert_eq!(
+            split_glob("./views/**/*.html"),
+            (PathBuf::from("./views"), "**/*.html".to_string())
+        );
+        assert_eq!(
+            split_glob("*.html"),
+            (PathBuf::from("."), "*.html".to_string())
+        );
+        assert_eq!(
+            split_glob("templates"),
+            (PathBuf::from("templates"), "**/*".to_string())
+        );
+    }
+
+    #[test]
+    fn finds_the_location_of_tera_errors() {
+        let err = Tera::default()
+            .add_raw_template("pages/index.html", "<p>\n{{ title")
+            .unwrap_err();
+        let (file, line) = error_location(&tera_error(err));
+        assert_eq!(file.as_deref(), Some("pages/index.html"));
+        assert_eq!(line, Some(2));
+    }
+
+    /// Templates every engine understands
+    const SHARED_TEMPLATES: &str = "tests/templates/engines/*";
+
+    fn assert_renders_greeting(mut engine: Box<dyn TemplateEngine>) {
+        engine.reload().unwrap();
+        assert!(engine.has_template("greeting.html"));
+
+        let mut context = Context::new();
+        context.insert("name", "<Ada>");
+        let html = engine.render("greeting.html", &context).unwrap();
+        assert_eq!(html.trim_end(), "<p>Hello &lt;Ada&gt;</p>");
+    }
+
+    #[test]
+    fn renders_with_tera() {
+        assert_renders_greeting(Engine::Tera.create(SHARED_TEMPLATES));
+    }
+
+    #[cfg(feature = "minijinja")]
+    #[test]
+    fn renders_with_minijinja() {
+        assert_renders_greeting(Engine::MiniJinja.create(SHARED_TEMPLATES));
+    }
+
+    #[cfg(feature = "handlebars")]
+    #[test]
+    fn renders_with_handlebars() {
+        assert_renders_greeting(Engine::Handlebars.create(SHARED_TEMPLATES));
+    }
+}
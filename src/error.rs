@@ -0,0 +1,172 @@
+//! Errors returned by Wenzetu
+//!
+//! [`Error`] tells apart the ways starting an app can fail, keeping the
+//! underlying errors as sources. [`exit_code`] turns the result of
+//! [`App::serve`](crate::App::serve) into a process exit code for `main`:
+//!
+//! ```rust,no_run
+//! use std::process::ExitCode;
+//! use wenzetu::App;
+//!
+//! #[tokio::main]
+//! async fn main() -> ExitCode {
+//!     wenzetu::error::exit_code(App::new().auto_config().serve().await)
+//! }
+//! ```
+
+use std::fmt;
+use std::io;
+use std::process::ExitCode;
+
+/// Boxed error kept as the source of an [`Error`]
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Errors returned when configuring or running an app
+#[derive(Debug)]
+pub enum Error {
+    /// Invalid or unreadable configuration, such as overlapping mounts
+    Config {
+        /// What is wrong with the configuration
+        message: String,
+        /// Underlying error, e.g. from parsing environment variables
+        source: Option<BoxError>,
+    },
+    /// The server address could not be bound, e.g. because it is in use.
+    /// Only errors from binding the listener, never from serving requests.
+    Bind(io::Error),
+    /// Templates failed to load
+    Template {
+        /// Template the error occurred in, when known
+        name: Option<String>,
+        /// Error reported by the template engine, with its causes
+        message: String,
+        /// The template engine's error
+        source: Option<BoxError>,
+    },
+    /// An [`on_startup`](crate::App::on_startup) hook failed
+    Startup(BoxError),
+    /// Other I/O errors
+    Io(io::Error),
+    /// The server failed while running
+    Server(BoxError),
+}
+
+impl Error {
+    /// Configuration error without an underlying error
+    pub fn config(message: impl Into<String>) -> Self {
+        Error::Config {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    /// Exit code for this error, following the BSD `sysexits.h` codes.
+    /// Startup hooks mostly fail on services that aren't reachable yet, so
+    /// `Startup` is `EX_TEMPFAIL` and a supervisor may retry.
+    ///
+    /// | Variant    | Code |
+    /// |------------|------|
+    /// | `Config`   | 78   |
+    /// | `Bind`     | 69   |
+    /// | `Template` | 65   |
+    /// | `Startup`  | 75   |
+    /// | `Io`       | 74   |
+    /// | `Server`   | 70   |
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Config { .. } => 78,
+            Error::Bind(_) => 69,
+            Error::Template { .. } => 65,
+            Error::Startup(_) => 75,
+            Error::Io(_) => 74,
+            Error::Server(_) => 70,
+        }
+    }
+
+    /// The error followed by its sources, one per line
+    pub fn report(&self) -> String {
+        let mut report = self.to_string();
+        let mut source = std::error::Error::source(self);
+        while let Some(err) = source {
+            report.push_str(&format!("\n  caused by: {}", err));
+            source = err.source();
+        }
+        report
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config { message, .. } => write!(f, "configuration error: {}", message),
+            Error::Bind(_) => write!(f, "failed to bind the server address"),
+            // The engine's error is the source, so only name the template
+            Error::Template {
+                name,
+                source: Some(_),
+                ..
+            } => match name {
+                Some(name) => write!(f, "failed to load template '{}'", name),
+                None => write!(f, "failed to load templates"),
+            },
+            Error::Template {
+                name: Some(name),
+                message,
+                ..
+            } => write!(f, "template error in '{}': {}", name, message),
+            Error::Template {
+                name: None,
+                message,
+                ..
+            } => write!(f, "template error: {}", message),
+            Error::Startup(_) => write!(f, "startup hook failed"),
+            Error::Io(_) => write!(f, "I/O error"),
+            Error::Server(_) => write!(f, "server error"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Config { source, .. } | Error::Template { source, .. } => {
+                source.as_deref().map(|err| err as _)
+            }
+            Error::Bind(err) | Error::Io(err) => Some(err),
+            Error::Startup(err) | Error::Server(err) => Some(err.as_ref()),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<config::ConfigError> for Error {
+    fn from(err: config::ConfigError) -> Self {
+        Error::Config {
+            message: "failed to load settings".to_string(),
+            source: Some(Box::new(err)),
+        }
+    }
+}
+
+impl From<&Error> for ExitCode {
+    fn from(err: &Error) -> Self {
+        ExitCode::from(err.exit_code())
+    }
+}
+
+/// Exit code for the result of `main`, printing the error and its sources
+/// to stderr on failure
+pub fn exit_code(result: Result<(), Error>) -> ExitCode {
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {}", err.report());
+            ExitCode::from(&err)
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Error pages rendered from templates
+//!
+//! [`ErrorPagesLayer`] replaces the errors Wenzetu answers with, such as the `404` of a
+//! missing static file or of a request no route matches, with a rendered
+//! template. Paths under the API prefix get a JSON error instead.
+//!
+//! Only responses marked with [`FrameworkError`] are replaced, so errors
+//! returned by handlers, even a bare `StatusCode::NOT_FOUND`, are left alone.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+
+use axum::response::IntoResponse;
+use bytes::Bytes;
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE, HeaderValue};
+use http::{Request, Response, StatusCode};
+use http_body_util::{Either, Full};
+use tera::Context;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::templates;
+
+/// Response extension marking an error produced by Wenzetu rather than a
+/// handler, such as a missing static file or a path no route matches.
+///
+/// [`ErrorPagesLayer`] only replaces responses carrying it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameworkError;
+
+/// Mark an error response as produced by Wenzetu
+pub(crate) fn mark<B>(mut response: Response<B>) -> Response<B> {
+    let status = response.status();
+    if status.is_client_error() || status.is_server_error() {
+        response.extensions_mut().insert(FrameworkError);
+    }
+    response
+}
+
+/// Fallback answering paths no route matches with a marked `404`
+pub(crate) async fn not_found() -> axum::response::Response {
+    mark(StatusCode::NOT_FOUND.into_response())
+}
+
+/// Layer rendering error templates for error responses marked with
+/// [`FrameworkError`]
+#[derive(Clone, Default)]
+pub struct ErrorPagesLayer {
+    pages: ErrorPages,
+}
+
+#[derive(Clone, Default)]
+struct ErrorPages {
+    /// Template name by status code
+    templates: HashMap<u16, String>,
+    api_prefix: Option<String>,
+}
+
+/// Request details available to error templates
+struct RequestInfo {
+    method: String,
+    path: String,
+    query: String,
+}
+
+impl ErrorPagesLayer {
+    /// A layer without templates, passing every response through
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Render `name` for responses with `status`.
+    ///
+    /// The template gets `status`, `reason`, `method`, `path` and `query`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use wenzetu::error_pages::ErrorPagesLayer;
+    ///
+    /// let layer = ErrorPagesLayer::new()
+    ///     .template(404, "errors/404.html")
+    ///     .template(500, "errors/500.html");
+    /// ```
+    pub fn template(mut self, status: u16, name: impl Into<String>) -> Self {
+        self.pages.templates.insert(status, name.into());
+        self
+    }
+
+    /// Answer errors under `prefix` with JSON instead of a template, e.g.
+    /// `{"error": "Not Found", "status": 404, "path": "/api/users"}`
+    pub fn api_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.pages.api_prefix = Some(prefix.into());
+        self
+    }
+}
+
+impl<S> Layer<S> for ErrorPagesLayer {
+    type Service = ErrorPagesService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ErrorPagesService {
+            inner,
+            pages: Arc::new(self.pages.clone()),
+        }
+    }
+}
+
+/// Service created by [`ErrorPagesLayer`]
+#[derive(Clone)]
+pub struct ErrorPagesService<S> {
+    inner: S,
+    pages: Arc<ErrorPages>,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for ErrorPagesService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Either<ResBody, Full<Bytes>>>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let request = RequestInfo {
+            method: req.method().to_string(),
+            path: req.uri().path().to_string(),
+            query: req.uri().query().unwrap_or_default().to_string(),
+        };
+        let future = self.inner.call(req);
+        let pages = self.pages.clone();
+
+        Box::pin(async move {
+            let response = future.await?;
+            Ok(pages.respond(response, &request))
+        })
+    }
+}
+
+impl ErrorPages {
+    fn respond<B>(
+        &self,
+        response: Response<B>,
+        request: &RequestInfo,
+    ) -> Response<Either<B, Full<Bytes>>> {
+        let status = response.status();
+        if response.extensions().get::<FrameworkError>().is_none() {
+            return response.map(Either::Left);
+        }
+
+        let reason = status.canonical_reason().unwrap_or_default();
+        let (content_type, body) = if self.is_api(&request.path) {
+            let error = serde_json::json!({
+                "error": reason,
+                "status": status.as_u16(),
+                "path": request.path,
+            });
+            ("application/json", error.to_string())
+        } else if let Some(name) = self.templates.get(&status.as_u16()) {
+            let mut context = Context::new();
+            context.insert("status", &status.as_u16());
+            context.insert("reason", reason);
+            context.insert("method", &request.method);
+            context.insert("path", &request.path);
+            context.insert("query", &request.query);
+            (
+                "text/html; charset=utf-8",
+                templates::render(name, &context),
+            )
+        } else {
+            return response.map(Either::Left);
+        };
+
+        let (mut parts, _) = response.into_parts();
+        parts
+            .headers
+            .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        parts.headers.remove(CONTENT_LENGTH);
+        Response::from_parts(parts, Either::Right(Full::new(Bytes::from(body))))
+    }
+
+    fn is_api(&self, path: &str) -> bool {
+        self.api_prefix.as_deref().is_some_and(|prefix| {
+            let prefix = prefix.trim_end_matches('/');
+            path == prefix || path.starts_with(&format!("{}/", prefix))
+        })
+    }
+}
@@ -0,0 +1,581 @@
+//! Resized images for static files
+//!
+//! With [`StaticConfig::images`](crate::app::StaticConfig::images), images of
+//! a static mount are also served resized under `_img/{width}x{height}/`:
+//!
+//! - `/public/_img/320x240/photos/cat.jpg` fits the image into 320×240
+//! - `?fit=cover` fills 320×240 exactly, cropping what overflows
+//! - `?format=webp` converts the image, `png` and `jpeg` work too
+//!
+//! Results are cached on disk and created again when the source changes.
+//! Only the sizes listed in [`ImageOptions`] are served, so clients can't
+//! make the server produce arbitrary variants, and only a few images are
+//! resized at once.
+
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::UNIX_EPOCH;
+
+use bytes::Bytes;
+use http::header::{CACHE_CONTROL, CONTENT_TYPE, ETAG, HeaderValue};
+use http::{Response, StatusCode};
+use http_body_util::{Either, Full};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, ImageReader};
+use tokio::sync::Semaphore;
+
+use crate::static_files::{StaticBody, content_hash, etag_matches};
+
+/// First path segment of resized image requests
+pub const IMAGE_PREFIX: &str = "_img";
+
+/// JPEG quality of resized images
+const JPEG_QUALITY: u8 = 85;
+
+/// Limits concurrent resizes to the number of CPUs, since decoding large
+/// images is CPU and memory heavy
+static RESIZES: LazyLock<Semaphore> = LazyLock::new(|| {
+    Semaphore::new(std::thread::available_parallelism().map_or(4, |count| count.get()))
+});
+
+/// Limits and cache of resized images
+#[derive(Debug, Clone)]
+pub struct ImageOptions {
+    /// Directory resized images are cached in, keep it outside the static
+    /// directory
+    pub cache_dir: PathBuf,
+    /// Sizes that can be requested. Nothing is served while it is empty.
+    pub sizes: Vec<(u32, u32)>,
+    /// Largest width or height that can be requested
+    pub max_dimension: u32,
+    /// Largest source file that is resized, in bytes
+    pub max_source_bytes: u64,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        Self {
+            cache_dir: PathBuf::from(".image-cache"),
+            sizes: Vec::new(),
+            max_dimension: 2048,
+            max_source_bytes: 20 * 1024 * 1024,
+        }
+    }
+}
+
+impl ImageOptions {
+    /// Cache resized images in `directory`
+    pub fn cache_dir(mut self, directory: impl Into<PathBuf>) -> Self {
+        self.cache_dir = directory.into();
+        self
+    }
+
+    /// Allow a size. Only listed sizes are served, so at least one is
+    /// required.
+    ///
+    /// # Example
+    /// ```rust
+    /// use wenzetu::images::ImageOptions;
+    ///
+    /// let options = ImageOptions::default().size(320, 240).size(1280, 720);
+    /// ```
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        self.sizes.push((width, height));
+        self
+    }
+
+    /// Set the largest width or height that can be requested
+    pub fn max_dimension(mut self, pixels: u32) -> Self {
+        self.max_dimension = pixels;
+        self
+    }
+
+    /// Set the largest source file that is resized, in bytes
+    pub fn max_source_bytes(mut self, bytes: u64) -> Self {
+        self.max_source_bytes = bytes;
+        self
+    }
+
+    /// Check a transform against the allowed sizes and the size limit
+    pub fn check(&self, transform: &Transform) -> Result<(), String> {
+        let size = (transform.width, transform.height);
+        if !self.sizes.contains(&size) {
+            return Err(format!("{}x{} is not an allowed size", size.0, size.1));
+        }
+        if size.0.max(size.1) > self.max_dimension {
+            return Err(format!(
+                "{}x{} exceeds the maximum of {} pixels",
+                size.0, size.1, self.max_dimension
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// How an image is fitted into the requested size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fit {
+    /// Scale down to fit inside the size, keeping the aspect ratio
+    Contain,
+    /// Scale to fill the size and crop the overflow
+    Cover,
+}
+
+/// Encoding of a resized image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Jpeg,
+    Png,
+    WebP,
+}
+
+impl OutputFormat {
+    /// Format for a file extension, e.g. `jpg` or `webp`
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "jpg" | "jpeg" => Some(Self::Jpeg),
+            "png" => Some(Self::Png),
+            "webp" => Some(Self::WebP),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::Png => "png",
+            Self::WebP => "webp",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::Png => "image/png",
+            Self::WebP => "image/webp",
+        }
+    }
+}
+
+/// Requested size, fit and format of an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transform {
+    pub width: u32,
+    pub height: u32,
+    pub fit: Fit,
+    /// Converted format, the source format when unset
+    pub format: Option<OutputFormat>,
+}
+
+impl Transform {
+    /// Parse a `320x240` size and the `fit` and `format` query parameters
+    pub fn parse(size: &str, query: Option<&str>) -> Result<Self, String> {
+        let parsed = size
+            .split_once('x')
+            .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+        let Some((width, height)) = parsed.filter(|&(width, height)| width > 0 && height > 0)
+        else {
+            return Err(format!("invalid image size '{}'", size));
+        };
+
+        let mut transform = Self {
+            width,
+            height,
+            fit: Fit::Contain,
+            format: None,
+        };
+        for (key, value) in query
+            .unwrap_or_default()
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+        {
+            match key {
+                "fit" if value == "contain" => transform.fit = Fit::Contain,
+                "fit" if value == "cover" => transform.fit = Fit::Cover,
+                "format" => {
+                    let format = OutputFormat::from_extension(value)
+                        .ok_or_else(|| format!("unsupported image format '{}'", value))?;
+                    transform.format = Some(format);
+                }
+                "fit" => return Err(format!("unsupported fit '{}'", value)),
+                // Other parameters, e.g. for cache busting
+                _ => {}
+            }
+        }
+        Ok(transform)
+    }
+
+    /// Format of the resized image
+    fn output_format(&self, source: &Path) -> OutputFormat {
+        self.format
+            .or_else(|| {
+                source
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .and_then(OutputFormat::from_extension)
+            })
+            .unwrap_or(OutputFormat::Png)
+    }
+
+    /// Cache location of the image resized from `source`
+    fn cache_path(&self, options: &ImageOptions, source: &Path) -> PathBuf {
+        let fit = match self.fit {
+            Fit::Contain => "contain",
+            Fit::Cover => "cover",
+        };
+        let mut path = options
+            .cache_dir
+            .join(format!("{}x{}-{}", self.width, self.height, fit))
+            .join(source);
+        if let Some(format) = self.format {
+            path.as_mut_os_string().push(".");
+            path.as_mut_os_string().push(format.extension());
+        }
+        path
+    }
+}
+
+/// Split a request path relative to the static directory into a transform
+/// and the source image, `None` when it isn't under [`IMAGE_PREFIX`]
+pub(crate) fn parse_request(
+    relative: &Path,
+    query: Option<&str>,
+) -> Option<Result<(Transform, PathBuf), String>> {
+    let mut components = relative.components();
+    if components.next()?.as_os_str() != IMAGE_PREFIX {
+        return None;
+    }
+    let size = components
+        .next()?
+        .as_os_str()
+        .to_string_lossy()
+        .into_owned();
+    let source = components.as_path().to_path_buf();
+    Some(Transform::parse(&size, query).map(|transform| (transform, source)))
+}
+
+/// Why an image couldn't be served
+#[derive(Debug)]
+pub(crate) enum ImageError {
+    NotFound,
+    /// The request or source is outside the limits
+    Rejected(String),
+    /// The source isn't an image format that can be decoded
+    Unsupported(String),
+    Failed(String),
+}
+
+/// A resized image in the cache
+#[derive(Debug)]
+pub(crate) struct CachedImage {
+    pub(crate) path: PathBuf,
+    /// Derived from the source's size and modification time and the
+    /// transform, so requests don't hash the image
+    pub(crate) etag: String,
+}
+
+/// Resize an image file, returning the encoded result
+pub fn resize_image(source: &Path, transform: &Transform) -> Result<Vec<u8>, String> {
+    let image = ImageReader::open(source)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|err| err.to_string())?
+        .decode()
+        .map_err(|err| err.to_string())?;
+
+    let (width, height) = (transform.width, transform.height);
+    let resized = match transform.fit {
+        // Never scale up
+        Fit::Contain if image.width() <= width && image.height() <= height => image,
+        Fit::Contain => image.resize(width, height, FilterType::Lanczos3),
+        Fit::Cover => image.resize_to_fill(width, height, FilterType::Lanczos3),
+    };
+
+    let mut encoded = Vec::new();
+    let result = match transform.output_format(source) {
+        OutputFormat::Jpeg => {
+            let encoder = JpegEncoder::new_with_quality(&mut encoded, JPEG_QUALITY);
+            DynamicImage::ImageRgb8(resized.to_rgb8()).write_with_encoder(encoder)
+        }
+        OutputFormat::Png => resized.write_to(&mut Cursor::new(&mut encoded), ImageFormat::Png),
+        OutputFormat::WebP => DynamicImage::ImageRgba8(resized.to_rgba8())
+            .write_to(&mut Cursor::new(&mut encoded), ImageFormat::WebP),
+    };
+    result.map_err(|err| err.to_string())?;
+    Ok(encoded)
+}
+
+/// The resized image in the cache, resizing it when missing or older than
+/// the source
+pub(crate) fn cached_image(
+    options: &ImageOptions,
+    directory: &Path,
+    source: &Path,
+    transform: &Transform,
+) -> Result<CachedImage, ImageError> {
+    options.check(transform).map_err(ImageError::Rejected)?;
+    let source_path = directory.join(source);
+    let meta = std::fs::metadata(&source_path)
+        .ok()
+        .filter(|meta| meta.is_file())
+        .ok_or(ImageError::NotFound)?;
+    if !ImageFormat::from_path(&source_path).is_ok_and(|format| format.reading_enabled()) {
+        return Err(ImageError::Unsupported(format!(
+            "{} is not a supported image",
+            source.display()
+        )));
+    }
+    if meta.len() > options.max_source_bytes {
+        return Err(ImageError::Rejected(format!(
+            "{} is larger than {} bytes",
+            source.display(),
+            options.max_source_bytes
+        )));
+    }
+
+    let modified = meta.modified().ok();
+    let mtime = modified
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    let etag = content_hash(
+        format!(
+            "{}:{}:{}:{:?}",
+            source.display(),
+            meta.len(),
+            mtime.as_nanos(),
+            transform
+        )
+        .as_bytes(),
+    );
+    let cached = CachedImage {
+        path: transform.cache_path(options, source),
+        etag,
+    };
+    let cached_modified = std::fs::metadata(&cached.path).and_then(|meta| meta.modified());
+    if let (Some(source_modified), Ok(cached_modified)) = (modified, cached_modified)
+        && cached_modified >= source_modified
+    {
+        return Ok(cached);
+    }
+
+    let encoded = resize_image(&source_path, transform).map_err(ImageError::Failed)?;
+    let write = || -> std::io::Result<()> {
+        let parent = cached.path.parent().unwrap_or(&options.cache_dir);
+        std::fs::create_dir_all(parent)?;
+        // Each writer gets its own temporary file, renamed into place, so
+        // concurrent requests for the same variant never read or clobber a
+        // partial file
+        let mut file = tempfile::NamedTempFile::new_in(parent)?;
+        file.write_all(&encoded)?;
+        file.persist(&cached.path).map_err(|err| err.error)?;
+        Ok(())
+    };
+    write().map_err(|err| ImageError::Failed(err.to_string()))?;
+    Ok(cached)
+}
+
+/// Response for a resized image request
+pub(crate) async fn respond(
+    options: ImageOptions,
+    directory: PathBuf,
+    source: PathBuf,
+    transform: Transform,
+    cache_control: Option<HeaderValue>,
+    if_none_match: Option<HeaderValue>,
+) -> Response<StaticBody> {
+    let format = transform.output_format(&source);
+    let display = source.display().to_string();
+    let result = match RESIZES.acquire().await {
+        Ok(_permit) => tokio::task::spawn_blocking(move || {
+            cached_image(&options, &directory, &source, &transform)
+        })
+        .await
+        .unwrap_or_else(|err| Err(ImageError::Failed(err.to_string()))),
+        Err(err) => Err(ImageError::Failed(err.to_string())),
+    };
+
+    let cached = match result {
+        Ok(cached) => cached,
+        Err(ImageError::NotFound) => return status_response(StatusCode::NOT_FOUND),
+        Err(ImageError::Rejected(reason)) => {
+            eprintln!("Rejected image request: {}", reason);
+            return status_response(StatusCode::BAD_REQUEST);
+        }
+        Err(ImageError::Unsupported(reason)) => {
+            eprintln!("Rejected image request: {}", reason);
+            return status_response(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        }
+        Err(ImageError::Failed(err)) => {
+            eprintln!("Failed to resize {}: {}", display, err);
+            return status_response(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    let etag = HeaderValue::from_str(&format!("\"{}\"", cached.etag)).ok();
+    let fresh = if_none_match
+        .zip(etag.as_ref())
+        .is_some_and(|(value, etag)| etag_matches(&value, etag));
+    let mut response = if fresh {
+        status_response(StatusCode::NOT_MODIFIED)
+    } else {
+        let content = match tokio::fs::read(&cached.path).await {
+            Ok(content) => content,
+            Err(err) => {
+                eprintln!("Failed to read the resized {}: {}", display, err);
+                return status_response(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        };
+        let mut response = Response::new(Either::Right(Full::new(Bytes::from(content))));
+        response.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static(format.content_type()),
+        );
+        response
+    };
+    if let Some(value) = cache_control {
+        response.headers_mut().insert(CACHE_CONTROL, value);
+    }
+    if let Some(etag) = etag {
+        response.headers_mut().insert(ETAG, etag);
+    }
+    response
+}
+
+fn status_response(status: StatusCode) -> Response<StaticBody> {
+    let mut response = Response::new(Either::Right(Full::new(Bytes::new())));
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(cache_dir: &Path) -> ImageOptions {
+        ImageOptions::default().cache_dir(cache_dir).size(2, 2)
+    }
+
+    #[test]
+    fn parses_image_requests() {
+        let (transform, source) = parse_request(
+            Path::new("_img/320x240/photos/cat.jpg"),
+            Some("fit=cover&format=webp"),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(source, Path::new("photos/cat.jpg"));
+        assert_eq!(
+            transform,
+            Transform {
+                width: 320,
+                height: 240,
+                fit: Fit::Cover,
+                format: Some(OutputFormat::WebP),
+            }
+        );
+
+        assert!(parse_request(Path::new("photos/cat.jpg"), None).is_none());
+        for (size, query) in [
+            ("0x240", None),
+            ("320", None),
+            ("320x240", Some("fit=fill")),
+        ] {
+            let path = PathBuf::from(format!("_img/{}/cat.jpg", size));
+            assert!(parse_request(&path, query).unwrap().is_err(), "{}", size);
+        }
+    }
+
+    #[test]
+    fn only_allowed_sizes_pass() {
+        let options = ImageOptions::default().size(320, 240).size(4096, 4096);
+        let transform = |width, height| Transform::parse(&format!("{}x{}", width, height), None);
+
+        assert!(options.check(&transform(320, 240).unwrap()).is_ok());
+        assert!(options.check(&transform(321, 240).unwrap()).is_err());
+        // Listed, but larger than `max_dimension`
+        assert!(options.check(&transform(4096, 4096).unwrap()).is_err());
+        assert!(
+            ImageOptions::default()
+                .check(&transform(320, 240).unwrap())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn cached_images_are_reused_until_the_source_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        DynamicImage::new_rgb8(4, 4)
+            .save(dir.path().join("dot.png"))
+            .unwrap();
+        let options = options(cache.path());
+        let transform = Transform::parse("2x2", None).unwrap();
+        let resize = || cached_image(&options, dir.path(), Path::new("dot.png"), &transform);
+
+        let first = resize().unwrap();
+        // A hit serves the cached file as it is
+        std::fs::write(&first.path, "cached").unwrap();
+        let second = resize().unwrap();
+        assert_eq!(second.path, first.path);
+        assert_eq!(second.etag, first.etag);
+        assert_eq!(std::fs::read(&second.path).unwrap(), b"cached");
+
+        // Editing the source makes a new variant
+        let source = std::fs::File::options()
+            .write(true)
+            .open(dir.path().join("dot.png"))
+            .unwrap();
+        source
+            .set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(60))
+            .unwrap();
+        let third = resize().unwrap();
+        assert_ne!(third.etag, first.etag);
+        assert_ne!(std::fs::read(&third.path).unwrap(), b"cached");
+    }
+
+    #[tokio::test]
+    async fn non_images_are_unsupported() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("app.css"), "body {}").unwrap();
+
+        let response = respond(
+            options(cache.path()),
+            dir.path().to_path_buf(),
+            PathBuf::from("app.css"),
+            Transform::parse("2x2", None).unwrap(),
+            None,
+            None,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn matching_etags_are_not_modified() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        DynamicImage::new_rgb8(4, 4)
+            .save(dir.path().join("dot.png"))
+            .unwrap();
+        let request = |if_none_match| {
+            respond(
+                options(cache.path()),
+                dir.path().to_path_buf(),
+                PathBuf::from("dot.png"),
+                Transform::parse("2x2", None).unwrap(),
+                None,
+                if_none_match,
+            )
+        };
+
+        let response = request(None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "image/png");
+        let etag = response.headers()[ETAG].clone();
+
+        let response = request(Some(etag)).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    }
+}
@@ -2,15 +2,37 @@
 //!
 
 pub mod app;
+pub mod assets;
 pub mod config;
+#[cfg(feature = "dev")]
+pub mod dev;
+#[cfg(feature = "email")]
+pub mod email;
+#[cfg(feature = "embedded-assets")]
+pub mod embedded;
+pub mod engine;
+pub mod error;
+pub mod error_pages;
 pub mod helpers;
+#[cfg(feature = "images")]
+pub mod images;
+#[cfg(feature = "live-reload")]
+pub mod live_reload;
+#[cfg(feature = "markdown")]
+pub mod markdown;
 pub mod prelude;
+pub mod shutdown;
+pub mod state;
 pub mod static_files;
 pub mod templates;
+pub mod typed;
 
 // Re-export commonly used types
+pub use tera;
 pub use uncovr;
 
 pub use app::App;
 pub use config::AppConfig;
+pub use error::Error;
 pub use templates::render;
+pub use typed::WenzetuTemplate;
@@ -0,0 +1,851 @@
+//! Live reload for templates and static assets
+//!
+//! One debounced watcher covers the template directory and every static
+//! directory. Template, script and image changes reload the page through
+//! [`LIVE_RELOADER`], while stylesheet changes are swapped in place by a small
+//! client script injected into rendered pages. When templates fail to load,
+//! the client script shows the error in an overlay instead of reloading, and
+//! clears it once the templates are fixed.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, SystemTime};
+
+use axum::extract::Query;
+use http::{HeaderMap, StatusCode};
+use notify_debouncer_mini::notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{DebounceEventResult, Debouncer, new_debouncer};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tower_livereload::{LiveReloadLayer, Reloader};
+use uncovr::prelude::ApiRouter;
+use uncovr::routing::{get, post};
+
+use crate::engine::{error_location, split_glob};
+use crate::templates;
+
+/// Path the client script listens on for live reload events
+pub const EVENTS_PATH: &str = "/_wenzetu/live-reload";
+
+/// Path the development runner posts to once a restarted app is up
+pub(crate) const DEV_RELOAD_PATH: &str = "/_wenzetu/dev-reload";
+
+/// Environment variable holding the token the development runner sends
+/// with its reload requests
+pub(crate) const DEV_TOKEN: &str = "WENZETU_DEV_TOKEN";
+
+/// Header carrying the token of a reload request
+pub(crate) const DEV_TOKEN_HEADER: &str = "x-wenzetu-dev-token";
+
+/// How long an events request waits before returning a `ping`
+const POLL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Files ignored by default: hidden files and editor swap/backup files
+pub const DEFAULT_IGNORE: [&str; 4] = [".*", "*~", "*.swp", "*.tmp"];
+
+/// Live reload layer, sharing its reloader with [`LIVE_RELOADER`]
+static LIVE_RELOAD_LAYER: LazyLock<LiveReloadLayer> = LazyLock::new(LiveReloadLayer::new);
+
+/// Live reload reloader (active in development only).
+pub static LIVE_RELOADER: LazyLock<Reloader> = LazyLock::new(|| LIVE_RELOAD_LAYER.reloader());
+
+/// Events pushed to the injected client script
+static EVENTS: LazyLock<broadcast::Sender<Sequenced>> = LazyLock::new(|| broadcast::channel(16).0);
+
+/// Most recent events, replayed to clients that missed them between polls
+static HISTORY: Mutex<VecDeque<Sequenced>> = Mutex::new(VecDeque::new());
+
+/// How many events [`HISTORY`] keeps
+const HISTORY_LEN: usize = 64;
+
+/// Live reload layer for easy integration
+pub fn live_reload_layer() -> LiveReloadLayer {
+    LIVE_RELOAD_LAYER.clone()
+}
+
+/// Event sent to the browser over [`EVENTS_PATH`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LiveReloadEvent {
+    /// A stylesheet changed and should be swapped without a reload
+    Css {
+        /// URL path of the stylesheet, e.g. `/public/app.css`
+        path: String,
+    },
+    /// Templates were reloaded successfully
+    Reloaded,
+    /// Templates failed to load, the page keeps the last working version
+    TemplateError {
+        /// Error reported by the template engine
+        message: String,
+        /// Template the error is in, if the engine reports it
+        file: Option<String>,
+        /// Line of the error, if the engine reports it
+        line: Option<u32>,
+    },
+    /// Templates load again after a [`LiveReloadEvent::TemplateError`]
+    Recovered,
+    /// Nothing happened before the request timed out
+    Ping,
+}
+
+impl LiveReloadEvent {
+    /// Build a `TemplateError` event, finding the file and line in the
+    /// message of any supported engine
+    pub fn template_error(message: impl Into<String>) -> Self {
+        let message = message.into();
+        let (file, line) = error_location(&message);
+        LiveReloadEvent::TemplateError {
+            message,
+            file,
+            line,
+        }
+    }
+}
+
+/// Push an event to every connected browser
+pub fn send(event: LiveReloadEvent) {
+    let Ok(mut history) = HISTORY.lock() else {
+        return;
+    };
+    let seq = history.back().map_or(1, |last| last.seq + 1);
+    let event = Sequenced { seq, event };
+    if history.len() == HISTORY_LEN {
+        history.pop_front();
+    }
+    history.push_back(event.clone());
+    // No receivers just means no page is open
+    let _ = EVENTS.send(event);
+}
+
+/// An event numbered in the order it was sent, so clients can ask for the
+/// events after the last one they saw
+#[derive(Debug, Clone, Serialize)]
+struct Sequenced {
+    seq: u64,
+    #[serde(flatten)]
+    event: LiveReloadEvent,
+}
+
+/// Number of the latest event, 0 before the first
+fn last_seq() -> u64 {
+    HISTORY
+        .lock()
+        .ok()
+        .and_then(|history| history.back().map(|last| last.seq))
+        .unwrap_or(0)
+}
+
+/// The first kept event after `since`
+fn event_after(since: u64) -> Option<Sequenced> {
+    let history = HISTORY.lock().ok()?;
+    history.iter().find(|event| event.seq > since).cloned()
+}
+
+/// What the live reload watcher should watch
+#[derive(Debug, Clone)]
+pub struct WatchConfig {
+    /// Template glob, e.g. `templates/**/*` or `views/{pages,layouts}/*.html`.
+    /// Only files matching it trigger a template reload
+    pub templates: Option<String>,
+    /// Static mounts as `(serve_path, directory)` pairs
+    pub static_dirs: Vec<(String, String)>,
+    /// Glob patterns of files to ignore, matched against the file name and
+    /// the path relative to the watched directory
+    pub ignore: Vec<String>,
+    /// Debounce delay shared by all watched directories
+    pub debounce: Duration,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self {
+            templates: None,
+            static_dirs: Vec::new(),
+            ignore: DEFAULT_IGNORE.iter().map(|p| p.to_string()).collect(),
+            debounce: Duration::from_millis(100),
+        }
+    }
+}
+
+/// A watched directory and what a change in it means
+struct Root {
+    directory: PathBuf,
+    kind: RootKind,
+}
+
+enum RootKind {
+    /// Only files matching `pattern`, relative to the directory, are templates
+    Templates {
+        pattern: glob::Pattern,
+    },
+    Static {
+        serve_path: String,
+    },
+}
+
+impl Root {
+    /// Whether a file at `relative` inside the directory belongs to this root
+    fn contains(&self, relative: &Path) -> bool {
+        match &self.kind {
+            RootKind::Templates { pattern } => pattern.matches_path_with(
+                relative,
+                glob::MatchOptions {
+                    require_literal_separator: true,
+                    ..glob::MatchOptions::new()
+                },
+            ),
+            RootKind::Static { .. } => true,
+        }
+    }
+}
+
+/// Start watching templates and static files.
+///
+/// The watcher runs until the returned handle is stopped or dropped.
+///
+/// # Example
+/// ```rust,no_run
+/// use wenzetu::live_reload::{WatchConfig, watch};
+///
+/// let watcher = watch(WatchConfig {
+///     templates: Some("templates/**/*".to_string()),
+///     static_dirs: vec![("/public".to_string(), "./public".to_string())],
+///     ..WatchConfig::default()
+/// })
+/// .unwrap();
+/// ```
+pub fn watch(config: WatchConfig) -> Result<WatcherHandle, String> {
+    let mut handle = WatcherHandle {
+        config,
+        debouncer: None,
+        last_error: Arc::new(Mutex::new(None)),
+    };
+    handle.start()?;
+    Ok(handle)
+}
+
+/// Handle to the watcher [`App::serve`](crate::App::serve) starts, shared
+/// with the app through [`App::watcher`](crate::App::watcher).
+///
+/// Clones control the same watcher. Until the app starts it, there is
+/// nothing to stop and no error to report; [`AppWatcher::restart`] starts
+/// one.
+#[derive(Clone, Default)]
+pub struct AppWatcher(Arc<Mutex<Option<WatcherHandle>>>);
+
+impl AppWatcher {
+    /// Start watching, replacing a previous watcher
+    pub(crate) fn start(&self, config: WatchConfig) -> Result<(), String> {
+        let handle = watch(config)?;
+        if let Ok(mut watcher) = self.0.lock() {
+            *watcher = Some(handle);
+        }
+        Ok(())
+    }
+
+    /// Whether the watcher is running
+    pub fn is_running(&self) -> bool {
+        self.0
+            .lock()
+            .is_ok_and(|watcher| watcher.as_ref().is_some_and(WatcherHandle::is_running))
+    }
+
+    /// The latest error reported by the watcher, if any. Kept after the
+    /// watcher stops.
+    pub fn last_error(&self) -> Option<String> {
+        self.0
+            .lock()
+            .ok()
+            .and_then(|watcher| watcher.as_ref().and_then(WatcherHandle::last_error))
+    }
+
+    /// The configuration of the current watcher, if one was started
+    pub fn config(&self) -> Option<WatchConfig> {
+        self.0
+            .lock()
+            .ok()
+            .and_then(|watcher| watcher.as_ref().map(|handle| handle.config().clone()))
+    }
+
+    /// Stop watching. Does nothing if the watcher isn't running.
+    pub fn stop(&self) {
+        if let Ok(mut watcher) = self.0.lock()
+            && let Some(handle) = watcher.as_mut()
+        {
+            handle.stop();
+        }
+    }
+
+    /// Restart the watcher with a new configuration, or start it if the app
+    /// hasn't yet, see [`WatcherHandle::restart`]
+    pub fn restart(&self, config: WatchConfig) -> Result<(), String> {
+        let mut watcher = self.0.lock().map_err(|err| err.to_string())?;
+        match watcher.as_mut() {
+            Some(handle) => handle.restart(config),
+            None => {
+                *watcher = Some(watch(config)?);
+                Ok(())
+            }
+        }
+    }
+
+    /// Load templates from a new path and watch it instead, see
+    /// [`WatcherHandle::set_templates`]
+    pub fn set_templates(&self, templates: impl Into<String>) -> Result<(), String> {
+        let config = WatchConfig {
+            templates: Some(templates.into()),
+            ..self.config().unwrap_or_default()
+        };
+        self.restart(config)
+    }
+}
+
+/// A running live reload watcher.
+///
+/// Dropping the handle stops the watcher. Errors reported while watching are
+/// printed and kept, see [`WatcherHandle::last_error`].
+pub struct WatcherHandle {
+    config: WatchConfig,
+    debouncer: Option<Debouncer<RecommendedWatcher>>,
+    last_error: Arc<Mutex<Option<String>>>,
+}
+
+impl WatcherHandle {
+    /// The configuration the watcher was started with
+    pub fn config(&self) -> &WatchConfig {
+        &self.config
+    }
+
+    /// Whether the watcher is running
+    pub fn is_running(&self) -> bool {
+        self.debouncer.is_some()
+    }
+
+    /// The latest error reported by the watcher, if any
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error.lock().ok().and_then(|err| err.clone())
+    }
+
+    /// Stop watching. Does nothing if the watcher is already stopped.
+    pub fn stop(&mut self) {
+        self.debouncer = None;
+    }
+
+    /// Stop the watcher and start it again with a new configuration.
+    ///
+    /// If the new configuration can't be watched, the watcher stays stopped
+    /// and the error is returned.
+    pub fn restart(&mut self, config: WatchConfig) -> Result<(), String> {
+        self.stop();
+        self.config = config;
+        self.start()
+    }
+
+    /// Load templates from a new path and restart the watcher for it
+    pub fn set_templates(&mut self, templates: impl Into<String>) -> Result<(), String> {
+        let templates = templates.into();
+        templates::init_templates(templates.clone());
+        // A load error is shown like any other, the path is still watched
+        let _ = templates::reload_templates();
+        let config = WatchConfig {
+            templates: Some(templates),
+            ..self.config.clone()
+        };
+        self.restart(config)
+    }
+
+    fn start(&mut self) -> Result<(), String> {
+        let result = self.spawn();
+        if let Err(err) = &result {
+            self.report(err.clone());
+        }
+        result
+    }
+
+    fn spawn(&mut self) -> Result<(), String> {
+        let mut roots = Vec::new();
+        if let Some(template_path) = &self.config.templates {
+            for pattern in expand_braces(template_path) {
+                let (directory, pattern) = split_glob(&pattern);
+                roots.push(Root {
+                    directory,
+                    kind: RootKind::Templates {
+                        pattern: glob::Pattern::new(&pattern).map_err(|err| {
+                            format!("invalid template path {}: {}", template_path, err)
+                        })?,
+                    },
+                });
+            }
+        }
+        for (serve_path, directory) in &self.config.static_dirs {
+            roots.push(Root {
+                directory: PathBuf::from(directory),
+                kind: RootKind::Static {
+                    serve_path: serve_path.trim_end_matches('/').to_string(),
+                },
+            });
+        }
+
+        // Events carry absolute paths, so compare against canonical roots
+        roots.retain_mut(|root| match root.directory.canonicalize() {
+            Ok(directory) => {
+                root.directory = directory;
+                true
+            }
+            Err(err) => {
+                self.report(format!(
+                    "not watching {}: {}",
+                    root.directory.display(),
+                    err
+                ));
+                false
+            }
+        });
+
+        let ignore = self
+            .config
+            .ignore
+            .iter()
+            .map(|pattern| glob::Pattern::new(pattern).map_err(|err| err.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Nested roots are already covered by the recursive watch of their parent
+        let mut watched: Vec<PathBuf> = roots.iter().map(|root| root.directory.clone()).collect();
+        watched.sort();
+        watched.dedup_by(|directory, parent| directory.starts_with(parent));
+        let mut modified = ModifiedTimes::scan(&watched);
+        let last_error = self.last_error.clone();
+
+        let mut debouncer = new_debouncer(
+            self.config.debounce,
+            move |result: DebounceEventResult| match result {
+                Ok(events) => {
+                    let paths: Vec<&Path> = events
+                        .iter()
+                        .map(|event| event.path.as_path())
+                        .filter(|path| modified.changed(path))
+                        .collect();
+                    handle_changes(&roots, &ignore, &paths);
+                }
+                Err(err) => record_error(&last_error, err.to_string()),
+            },
+        )
+        .map_err(|err| err.to_string())?;
+
+        for directory in &watched {
+            debouncer
+                .watcher()
+                .watch(directory, RecursiveMode::Recursive)
+                .map_err(|err| format!("failed to watch {}: {}", directory.display(), err))?;
+        }
+
+        self.debouncer = Some(debouncer);
+        Ok(())
+    }
+
+    fn report(&self, err: String) {
+        record_error(&self.last_error, err);
+    }
+}
+
+fn record_error(last_error: &Mutex<Option<String>>, err: String) {
+    eprintln!("Live reload watcher error: {}", err);
+    if let Ok(mut lock) = last_error.lock() {
+        *lock = Some(err);
+    }
+}
+
+/// Last seen modification time of every watched file.
+///
+/// Some platforms report reads as events, so a file only counts as changed
+/// when its modification time moves, otherwise reloading templates would
+/// trigger another reload.
+pub(crate) struct ModifiedTimes(HashMap<PathBuf, SystemTime>);
+
+impl ModifiedTimes {
+    /// Record every file under the given files or directories
+    pub(crate) fn scan(paths: &[PathBuf]) -> Self {
+        let mut times = HashMap::new();
+        let mut pending = paths.to_vec();
+        while let Some(path) = pending.pop() {
+            match std::fs::metadata(&path) {
+                Ok(meta) if meta.is_dir() => {
+                    if let Ok(entries) = std::fs::read_dir(&path) {
+                        pending.extend(entries.flatten().map(|entry| entry.path()));
+                    }
+                }
+                Ok(meta) => {
+                    if let Ok(time) = meta.modified() {
+                        times.insert(path, time);
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+        Self(times)
+    }
+
+    /// Whether `path` changed since it was last seen
+    pub(crate) fn changed(&mut self, path: &Path) -> bool {
+        match std::fs::metadata(path) {
+            Ok(meta) if meta.is_dir() => false,
+            Ok(meta) => {
+                let time = meta.modified().ok();
+                let previous = time.and_then(|time| self.0.insert(path.to_path_buf(), time));
+                time.is_none() || previous != time
+            }
+            // Removed files only count if they were there before
+            Err(_) => self.0.remove(path).is_some(),
+        }
+    }
+}
+
+/// Reload templates or notify the browser for a batch of changed files
+fn handle_changes(roots: &[Root], ignore: &[glob::Pattern], paths: &[&Path]) {
+    if paths.is_empty() {
+        return;
+    }
+
+    let mut reload_templates = false;
+    let mut reload_page = false;
+    let mut rescan_assets = false;
+    let mut stylesheets = Vec::new();
+
+    for path in paths {
+        let Some((root, relative)) = roots.iter().find_map(|root| {
+            let relative = path.strip_prefix(&root.directory).ok()?;
+            root.contains(relative).then_some((root, relative))
+        }) else {
+            continue;
+        };
+
+        if is_ignored(ignore, relative) {
+            continue;
+        }
+
+        match &root.kind {
+            RootKind::Templates { .. } => reload_templates = true,
+            RootKind::Static { serve_path } => {
+                rescan_assets = true;
+                if relative.extension().is_some_and(|ext| ext == "css") {
+                    let url_path = relative.to_string_lossy().replace('\\', "/");
+                    stylesheets.push(format!("{}/{}", serve_path, url_path));
+                } else {
+                    reload_page = true;
+                }
+            }
+        }
+    }
+
+    // Fingerprints change with the content
+    if rescan_assets {
+        crate::assets::rescan_assets();
+    }
+
+    let template_error = reload_templates && !reload_template_engine();
+    send_page_events(reload_templates || reload_page, template_error, stylesheets);
+}
+
+/// Reload the templates and tell the client, returning whether they loaded
+fn reload_template_engine() -> bool {
+    let had_error = templates::TERA_INIT_ERROR
+        .read()
+        .is_ok_and(|err| err.is_some());
+
+    match templates::reload_templates() {
+        Ok(()) if had_error => send(LiveReloadEvent::Recovered),
+        Ok(()) => send(LiveReloadEvent::Reloaded),
+        Err(err) => {
+            send(LiveReloadEvent::template_error(err));
+            return false;
+        }
+    }
+    true
+}
+
+/// Reload the page, or swap the changed stylesheets when it is kept. A
+/// template error keeps the page and shows the error over it, stylesheet
+/// changes in the same batch still apply.
+fn send_page_events(reload: bool, template_error: bool, stylesheets: Vec<String>) {
+    if reload && !template_error {
+        LIVE_RELOADER.reload();
+        return;
+    }
+    for path in stylesheets {
+        send(LiveReloadEvent::Css { path });
+    }
+}
+
+/// Expand `{a,b}` alternatives, which Tera accepts in template paths, into
+/// one glob per alternative
+fn expand_braces(glob: &str) -> Vec<String> {
+    let Some(start) = glob.find('{') else {
+        return vec![glob.to_string()];
+    };
+    let Some(end) = glob[start..].find('}').map(|end| start + end) else {
+        return vec![glob.to_string()];
+    };
+
+    glob[start + 1..end]
+        .split(',')
+        .flat_map(|alternative| {
+            expand_braces(&format!(
+                "{}{}{}",
+                &glob[..start],
+                alternative,
+                &glob[end + 1..]
+            ))
+        })
+        .collect()
+}
+
+fn is_ignored(ignore: &[glob::Pattern], relative: &Path) -> bool {
+    let file_name = relative.file_name().map(Path::new);
+    ignore.iter().any(|pattern| {
+        pattern.matches_path(relative) || file_name.is_some_and(|name| pattern.matches_path(name))
+    })
+}
+
+/// Route the injected client script polls for events, plus the reload
+/// route of the development runner when the app runs under it
+pub fn routes() -> ApiRouter {
+    let routes = ApiRouter::new().route(EVENTS_PATH, get(poll_events));
+    match std::env::var(DEV_TOKEN) {
+        Ok(token) => routes.route(
+            DEV_RELOAD_PATH,
+            post(move |headers: HeaderMap| dev_reload(headers, token)),
+        ),
+        Err(_) => routes,
+    }
+}
+
+/// Reload the pages of a restarted app, for requests carrying the token
+/// of the development runner
+async fn dev_reload(headers: HeaderMap, token: String) -> StatusCode {
+    if headers
+        .get(DEV_TOKEN_HEADER)
+        .is_none_or(|value| value != token.as_str())
+    {
+        return StatusCode::FORBIDDEN;
+    }
+    LIVE_RELOADER.reload();
+    StatusCode::NO_CONTENT
+}
+
+/// Query of an events request
+#[derive(Deserialize)]
+struct PollQuery {
+    /// Number of the last event the client saw
+    since: Option<u64>,
+}
+
+/// Answer with the first event after `since`, waiting for one up to
+/// [`POLL_TIMEOUT`] before answering with a `ping`
+async fn poll_events(Query(query): Query<PollQuery>) -> String {
+    // Subscribe before looking at the history so no event falls in between
+    let mut receiver = EVENTS.subscribe();
+    // Numbers start over when the server restarts
+    let since = query
+        .since
+        .map_or_else(last_seq, |since| since.min(last_seq()));
+
+    let wait = async {
+        if let Some(event) = event_after(since) {
+            return event;
+        }
+        loop {
+            match receiver.recv().await {
+                Ok(event) if event.seq > since => return event,
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    if let Some(event) = event_after(since) {
+                        return event;
+                    }
+                }
+                Err(broadcast::error::RecvError::Closed) => std::future::pending().await,
+            }
+        }
+    };
+    let event = tokio::time::timeout(POLL_TIMEOUT, wait)
+        .await
+        .unwrap_or(Sequenced {
+            seq: since,
+            event: LiveReloadEvent::Ping,
+        });
+    serde_json::to_string(&event).unwrap_or_default()
+}
+
+/// Client script swapping stylesheets on `css` events and showing template
+/// errors in an overlay
+const CLIENT_SCRIPT: &str = r#"<script>
+(function () {
+  var OVERLAY_ID = "wenzetu-error-overlay";
+  function swap(path) {
+    var links = document.querySelectorAll('link[rel="stylesheet"]');
+    var matched = Array.prototype.filter.call(links, function (link) {
+      return new URL(link.href).pathname === path;
+    });
+    (matched.length ? matched : links).forEach(function (link) {
+      var url = new URL(link.href);
+      url.searchParams.set("wenzetu", Date.now());
+      link.href = url.toString();
+    });
+  }
+  function hideError() {
+    var overlay = document.getElementById(OVERLAY_ID);
+    if (overlay) overlay.remove();
+  }
+  function showError(event) {
+    hideError();
+    var overlay = document.createElement("div");
+    overlay.id = OVERLAY_ID;
+    overlay.style.cssText = "position:fixed;inset:0;z-index:2147483647;overflow:auto;" +
+      "background:rgba(0,0,0,.85);color:#eee;font:14px/1.5 monospace;padding:32px;";
+    var title = document.createElement("div");
+    title.style.cssText = "color:#ff5555;font-size:18px;margin-bottom:8px;";
+    title.textContent = "Template error";
+    var location = document.createElement("div");
+    location.style.cssText = "color:#aaa;margin-bottom:16px;";
+    location.textContent = event.file ? event.file + (event.line ? ":" + event.line : "") : "";
+    var message = document.createElement("pre");
+    message.style.cssText = "white-space:pre-wrap;margin:0;";
+    message.textContent = event.message;
+    var hint = document.createElement("div");
+    hint.style.cssText = "color:#aaa;margin-top:16px;";
+    hint.textContent = "Fix the template to dismiss, or click to close.";
+    overlay.append(title, location, message, hint);
+    overlay.addEventListener("click", hideError);
+    document.body.appendChild(overlay);
+  }
+  var since = __SINCE__;
+  function poll() {
+    fetch("__EVENTS_PATH__?since=" + since)
+      .then(function (res) { return res.json(); })
+      .then(function (event) {
+        since = event.seq;
+        if (event.type === "css") swap(event.path);
+        if (event.type === "template_error") showError(event);
+        if (event.type === "reloaded" || event.type === "recovered") hideError();
+        poll();
+      })
+      .catch(function () { setTimeout(poll, 1000); });
+  }
+  poll();
+})();
+</script>"#;
+
+/// Add the client script before the `</body>` of a rendered HTML page.
+/// Fragments without one, e.g. partials fetched by scripts, are left as
+/// they are.
+pub(crate) fn inject_client(mut html: String) -> String {
+    if let Some(index) = html.to_ascii_lowercase().rfind("</body>") {
+        let script = CLIENT_SCRIPT
+            .replace("__EVENTS_PATH__", EVENTS_PATH)
+            .replace("__SINCE__", &last_seq().to_string());
+        html.insert_str(index, &script);
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn poll(since: u64) -> serde_json::Value {
+        let query = Query(PollQuery { since: Some(since) });
+        serde_json::from_str(&poll_events(query).await).unwrap()
+    }
+
+    #[tokio::test]
+    async fn events_sent_between_polls_are_not_lost() {
+        let mut since = last_seq();
+        send(LiveReloadEvent::Css {
+            path: "/public/first.css".to_string(),
+        });
+        send(LiveReloadEvent::Css {
+            path: "/public/second.css".to_string(),
+        });
+
+        // Other tests may send events too, only the order of these matters
+        let mut paths = Vec::new();
+        while paths.len() < 2 {
+            let event = poll(since).await;
+            since = event["seq"].as_u64().unwrap();
+            if let Some(path) = event["path"].as_str() {
+                paths.push(path.to_string());
+            }
+        }
+        assert_eq!(paths, ["/public/first.css", "/public/second.css"]);
+    }
+
+    #[tokio::test]
+    async fn dev_reloads_need_the_runner_token() {
+        let reload = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(DEV_TOKEN_HEADER, value.parse().unwrap());
+            dev_reload(headers, "secret".to_string())
+        };
+
+        assert_eq!(reload("guess").await, StatusCode::FORBIDDEN);
+        assert_eq!(
+            dev_reload(HeaderMap::new(), "secret".to_string()).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(reload("secret").await, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn template_errors_keep_stylesheet_changes() {
+        let mut since = last_seq();
+        send_page_events(true, true, vec!["/public/site.css".to_string()]);
+
+        // Other tests may send events too
+        loop {
+            let event = poll(since).await;
+            since = event["seq"].as_u64().unwrap();
+            if event["path"] == "/public/site.css" {
+                assert_eq!(event["type"], "css");
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn reports_a_deleted_root_after_restarting() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().display().to_string();
+        let config = WatchConfig {
+            static_dirs: vec![("/public".to_string(), root.clone())],
+            ..WatchConfig::default()
+        };
+
+        let watcher = AppWatcher::default();
+        watcher.restart(config.clone()).unwrap();
+        assert!(watcher.is_running());
+        assert_eq!(watcher.last_error(), None);
+
+        watcher.stop();
+        assert!(!watcher.is_running());
+
+        dir.close().unwrap();
+        watcher.restart(config).unwrap();
+        let err = watcher.last_error().unwrap();
+        assert!(err.contains(&root), "{}", err);
+    }
+
+    #[test]
+    fn injects_the_client_before_the_closing_body_tag() {
+        let html = inject_client("<html><BODY><p>Hi</p></BODY></html>".to_string());
+        assert!(html.contains("<p>Hi</p><script>"));
+        assert!(html.ends_with("</script></BODY></html>"));
+        assert!(!html.contains("__SINCE__"));
+    }
+
+    #[test]
+    fn leaves_fragments_without_a_body_alone() {
+        let fragment = "<li>Item</li>".to_string();
+        assert_eq!(inject_client(fragment.clone()), fragment);
+    }
+}
@@ -0,0 +1,226 @@
+//! Markdown content rendering
+//!
+//! Renders Markdown files with YAML (`---`) or TOML (`+++`) front matter into
+//! sanitized HTML inside a layout template.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, RwLock};
+use std::time::SystemTime;
+
+use pulldown_cmark::{Options, Parser, html};
+use serde_json::Value;
+use tera::Context;
+
+use crate::templates;
+
+/// Most pages kept in [`PAGES`]
+const MAX_PAGES: usize = 1024;
+
+/// Parsed Markdown pages, keyed by path and invalidated when the file changes
+static PAGES: LazyLock<RwLock<HashMap<PathBuf, (SystemTime, Page)>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// A parsed Markdown page
+#[derive(Debug, Clone)]
+pub struct Page {
+    /// Front matter fields
+    pub front_matter: serde_json::Map<String, Value>,
+    /// Sanitized HTML body
+    pub html: String,
+}
+
+/// Render Markdown to sanitized HTML.
+///
+/// # Example
+/// ```rust
+/// use wenzetu::markdown::markdown_to_html;
+///
+/// let html = markdown_to_html("# Hello\n\n<script>alert(1)</script>");
+/// assert!(html.contains("<h1>Hello</h1>"));
+/// assert!(!html.contains("<script>"));
+/// ```
+pub fn markdown_to_html(markdown: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_HEADING_ATTRIBUTES;
+
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, Parser::new_ext(markdown, options));
+
+    ammonia::Builder::default()
+        // Keep `language-*` classes for syntax highlighting
+        .add_tag_attributes("code", &["class"])
+        .add_generic_attributes(&["id"])
+        // Task list items, any other input becomes a checkbox too
+        .add_tags(&["input"])
+        .add_tag_attributes("input", &["checked", "disabled"])
+        .set_tag_attribute_value("input", "type", "checkbox")
+        .clean(&unsafe_html)
+        .to_string()
+}
+
+/// Tera filter rendering a string as Markdown: `{{ body | markdown }}`.
+///
+/// The output is already sanitized, so it is not escaped again.
+pub struct MarkdownFilter;
+
+impl tera::Filter for MarkdownFilter {
+    fn filter(&self, value: &Value, _args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let markdown = value.as_str().ok_or_else(|| {
+            tera::Error::msg("Filter `markdown` was called on a non-string value")
+        })?;
+        Ok(Value::String(markdown_to_html(markdown)))
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+/// Split a document into front matter and body, then parse both.
+///
+/// # Example
+/// ```rust
+/// use wenzetu::markdown::parse_page;
+///
+/// let page = parse_page("---\ntitle: Hello\n---\n# Hi").unwrap();
+/// assert_eq!(page.front_matter["title"], "Hello");
+/// ```
+pub fn parse_page(source: &str) -> Result<Page, String> {
+    let (front_matter, body) = match split_front_matter(source, "---") {
+        Some((raw, body)) => {
+            let value: Value = serde_yaml::from_str(raw)
+                .map_err(|err| format!("invalid YAML front matter: {}", err))?;
+            (value, body)
+        }
+        None => match split_front_matter(source, "+++") {
+            Some((raw, body)) => {
+                let value: Value = toml::from_str(raw)
+                    .map_err(|err| format!("invalid TOML front matter: {}", err))?;
+                (value, body)
+            }
+            None => (Value::Null, source),
+        },
+    };
+
+    let front_matter = match front_matter {
+        Value::Object(map) => map,
+        Value::Null => serde_json::Map::new(),
+        _ => return Err("front matter must be a map of fields".to_string()),
+    };
+
+    Ok(Page {
+        front_matter,
+        html: markdown_to_html(body),
+    })
+}
+
+/// Load a Markdown page from disk, reusing the parsed page until the file
+/// is modified
+pub fn load_page(path: impl AsRef<Path>) -> Result<Page, String> {
+    let path = path.as_ref();
+    let modified = std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+
+    if let Ok(pages) = PAGES.read()
+        && let Some((cached_at, page)) = pages.get(path)
+        && *cached_at == modified
+    {
+        return Ok(page.clone());
+    }
+
+    let source = std::fs::read_to_string(path)
+        .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+    let page = parse_page(&source)?;
+
+    if let Ok(mut pages) = PAGES.write() {
+        // Forget an arbitrary page rather than growing without bound
+        if pages.len() >= MAX_PAGES
+            && !pages.contains_key(path)
+            && let Some(evicted) = pages.keys().next().cloned()
+        {
+            pages.remove(&evicted);
+        }
+        pages.insert(path.to_path_buf(), (modified, page.clone()));
+    }
+    Ok(page)
+}
+
+/// Render a Markdown file inside a layout template.
+///
+/// Front matter fields are added to the context (values from `context`
+/// take precedence) and the rendered HTML is available as `content`, so the
+/// layout should output it with `{{ content | safe }}`. Edits to the file
+/// are picked up on the next render.
+///
+/// # Example
+/// ```rust,no_run
+/// use wenzetu::{context, markdown::render_markdown_page};
+///
+/// let html = render_markdown_page(
+///     "content/blog/hello.md",
+///     "layouts/post.html",
+///     &context! { site_name: "My Blog" },
+/// );
+/// ```
+pub fn render_markdown_page(path: impl AsRef<Path>, layout: &str, context: &Context) -> String {
+    let path = path.as_ref();
+    let page = match load_page(path) {
+        Ok(page) => page,
+        Err(err) => {
+            eprintln!("Markdown error in '{}': {}", path.display(), err);
+            return templates::render_error_page(&path.display().to_string(), &err);
+        }
+    };
+
+    let mut page_context = Context::new();
+    for (key, value) in page.front_matter {
+        page_context.insert(key, &value);
+    }
+    page_context.extend(context.clone());
+    page_context.insert("content", &page.html);
+
+    templates::render(layout, &page_context)
+}
+
+/// Returns `(front matter, body)` if `source` starts with a `delimiter` block
+fn split_front_matter<'a>(source: &'a str, delimiter: &str) -> Option<(&'a str, &'a str)> {
+    let source = source.strip_prefix('\u{feff}').unwrap_or(source);
+    let rest = source
+        .strip_prefix(delimiter)?
+        .strip_prefix("\r\n")
+        .or_else(|| source.strip_prefix(delimiter)?.strip_prefix('\n'))?;
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == delimiter {
+            return Some((&rest[..offset], &rest[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_task_lists_as_checkboxes() {
+        let html = markdown_to_html("- [x] done\n- [ ] todo");
+        assert_eq!(html.matches(r#"type="checkbox""#).count(), 2, "{}", html);
+        assert_eq!(html.matches("disabled").count(), 2, "{}", html);
+        assert_eq!(html.matches("checked").count(), 1, "{}", html);
+    }
+
+    #[test]
+    fn other_inputs_become_checkboxes() {
+        let html = markdown_to_html(r#"<input type="password" name="secret" onfocus="alert(1)">"#);
+        assert!(html.contains(r#"type="checkbox""#), "{}", html);
+        assert!(!html.contains("password") && !html.contains("name=") && !html.contains("onfocus"));
+    }
+}
@@ -11,8 +11,10 @@ pub use uncovr::prelude::*;
 // Re-export from wenzetu
 pub use crate::app::{self, App};
 pub use crate::config::{AppConfig, load_config, to_uncovr_config};
+pub use crate::engine::{Engine, TemplateEngine};
 pub use crate::helpers;
 pub use crate::templates::{TEMPLATES, render};
+pub use crate::typed::WenzetuTemplate;
 pub use crate::{context, static_files};
 
 // Re-export Tera for context building
@@ -0,0 +1,182 @@
+//! Graceful shutdown
+//!
+//! [`App::serve`](crate::App::serve) serves through [`serve`]. When
+//! [`App::graceful_shutdown`](crate::App::graceful_shutdown) is set, on
+//! SIGINT or SIGTERM the server stops accepting connections, waits for
+//! in-flight requests up to a timeout, and then runs the shutdown hooks.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::Router;
+use tokio::net::TcpListener;
+use tokio::sync::Notify;
+
+/// Async hook run when the server shuts down
+pub(crate) type ShutdownHook = Box<dyn FnOnce() -> Pin<Box<dyn Future<Output = ()> + Send>> + Send>;
+
+/// Box an async closure as a [`ShutdownHook`]
+pub(crate) fn hook<F, Fut>(hook: F) -> ShutdownHook
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    Box::new(move || Box::pin(hook()))
+}
+
+/// Wait for Ctrl+C, or SIGTERM on Unix
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            eprintln!("Failed to listen for Ctrl+C: {}", err);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(err) => {
+                eprintln!("Failed to listen for SIGTERM: {}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+/// Serve `router` on `listener`. With a `timeout`, stop on [`signal`] and
+/// wait up to `timeout` for in-flight requests before dropping the remaining
+/// connections; without one, run until the process is killed.
+pub(crate) async fn serve(
+    listener: TcpListener,
+    router: Router,
+    timeout: Option<Duration>,
+) -> io::Result<()> {
+    match timeout {
+        Some(timeout) => serve_until(listener, router, timeout, signal()).await,
+        None => axum::serve(listener, router).await,
+    }
+}
+
+/// Serve until `shutdown` completes, then wait up to `timeout` for
+/// in-flight requests
+async fn serve_until(
+    listener: TcpListener,
+    router: Router,
+    timeout: Duration,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> io::Result<()> {
+    let stopping = Arc::new(Notify::new());
+    let notify = stopping.clone();
+    let server = axum::serve(listener, router).with_graceful_shutdown(async move {
+        shutdown.await;
+        notify.notify_one();
+    });
+    let mut server = std::pin::pin!(server.into_future());
+
+    tokio::select! {
+        result = &mut server => return result,
+        _ = stopping.notified() => {}
+    }
+
+    eprintln!(
+        "Shutting down, waiting up to {:?} for open requests",
+        timeout
+    );
+    match tokio::time::timeout(timeout, server).await {
+        Ok(result) => result,
+        Err(_) => {
+            eprintln!("Shutdown timeout reached, closing the remaining connections");
+            Ok(())
+        }
+    }
+}
+
+/// Run hooks in reverse registration order, like destructors
+pub(crate) async fn run_hooks(hooks: Vec<ShutdownHook>) {
+    for hook in hooks.into_iter().rev() {
+        hook().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    use axum::routing::get;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpStream;
+    use tokio::sync::oneshot;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn slow_requests_are_cut_off_after_the_timeout() {
+        let started = Arc::new(Notify::new());
+        let handler_started = started.clone();
+        let router = Router::new().route(
+            "/slow",
+            get(move || async move {
+                handler_started.notify_one();
+                tokio::time::sleep(Duration::from_secs(60)).await;
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let timeout = Duration::from_millis(200);
+        let server = tokio::spawn(serve_until(listener, router, timeout, async {
+            let _ = stopped.await;
+        }));
+
+        let mut client = TcpStream::connect(address).await.unwrap();
+        client
+            .write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        started.notified().await;
+
+        let stopping = Instant::now();
+        stop.send(()).unwrap();
+        let result = tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("the server should stop after the drain timeout")
+            .unwrap();
+
+        assert!(result.is_ok());
+        assert!(stopping.elapsed() >= timeout);
+    }
+
+    #[tokio::test]
+    async fn hooks_run_in_reverse_registration_order() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let hooks = ["first", "second", "third"]
+            .into_iter()
+            .map(|name| {
+                let calls = calls.clone();
+                hook(move || async move { calls.lock().unwrap().push(name) })
+            })
+            .collect();
+
+        run_hooks(hooks).await;
+
+        assert_eq!(*calls.lock().unwrap(), ["third", "second", "first"]);
+    }
+}
@@ -0,0 +1,195 @@
+//! Shared application state
+//!
+//! Values added with [`App::state`](crate::App::state) are shared with every
+//! request and extracted in handlers with [`Shared`]. The resolved
+//! [`AppConfig`](crate::AppConfig) and a
+//! [`Templates`](crate::templates::Templates) handle are always available.
+//!
+//! [`Shared`] is separate from axum's `State`: it reads the values added to
+//! the app, not the state given to `Router::with_state`, so both can be
+//! used side by side.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+
+use axum::extract::FromRequestParts;
+use axum::response::{IntoResponse, Response};
+use http::request::Parts;
+use http::{Extensions, Request, StatusCode};
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// Extractor for a value added with [`App::state`](crate::App::state)
+///
+/// # Example
+/// ```rust,ignore
+/// use wenzetu::state::Shared;
+///
+/// async fn users(Shared(db): Shared<Db>, Shared(config): Shared<AppConfig>) -> String {
+///     format!("{} users on {}", db.count().await, config.app.name)
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Shared<T>(pub T);
+
+impl<T> Deref for Shared<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Shared<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T, S> FromRequestParts<S> for Shared<T>
+where
+    T: Clone + Send + Sync + 'static,
+    S: Send + Sync,
+{
+    type Rejection = MissingState;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<SharedValues>()
+            .and_then(|shared| shared.0.get::<T>())
+            .cloned()
+            .map(Shared)
+            .ok_or(MissingState(std::any::type_name::<T>()))
+    }
+}
+
+/// Rejection for a [`Shared`] value that was never added, answered with
+/// `500 Internal Server Error`
+#[derive(Debug)]
+pub struct MissingState(&'static str);
+
+impl IntoResponse for MissingState {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Missing state `{}`, add it with `App::state()`", self.0),
+        )
+            .into_response()
+    }
+}
+
+/// The values of a [`StateLayer`], put into every request as one extension
+#[derive(Clone)]
+struct SharedValues(Arc<Extensions>);
+
+/// Layer sharing state with every request
+#[derive(Clone, Default)]
+pub struct StateLayer {
+    state: Arc<Extensions>,
+}
+
+impl StateLayer {
+    /// A layer sharing every value in `state`
+    pub fn new(state: Extensions) -> Self {
+        Self {
+            state: Arc::new(state),
+        }
+    }
+}
+
+impl<S> Layer<S> for StateLayer {
+    type Service = StateService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        StateService {
+            inner,
+            state: self.state.clone(),
+        }
+    }
+}
+
+/// Service created by [`StateLayer`]
+#[derive(Clone)]
+pub struct StateService<S> {
+    inner: S,
+    state: Arc<Extensions>,
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for StateService<S>
+where
+    S: Service<Request<ReqBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        req.extensions_mut()
+            .insert(SharedValues(self.state.clone()));
+        self.inner.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::future::{Ready, ready};
+
+    use super::*;
+
+    /// Service returning the request's extensions
+    struct Echo;
+
+    impl Service<Request<()>> for Echo {
+        type Response = Parts;
+        type Error = Infallible;
+        type Future = Ready<Result<Parts, Infallible>>;
+
+        fn poll_ready(&mut self, _cx: &mut TaskContext<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: Request<()>) -> Self::Future {
+            ready(Ok(req.into_parts().0))
+        }
+    }
+
+    async fn parts_with(state: Extensions) -> Parts {
+        let mut service = StateLayer::new(state).layer(Echo);
+        service.call(Request::new(())).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn extracts_shared_values() {
+        let mut state = Extensions::new();
+        state.insert(42_u32);
+        state.insert("app".to_string());
+        let mut parts = parts_with(state).await;
+
+        let Shared(number) = Shared::<u32>::from_request_parts(&mut parts, &())
+            .await
+            .unwrap();
+        let Shared(name) = Shared::<String>::from_request_parts(&mut parts, &())
+            .await
+            .unwrap();
+        assert_eq!(number, 42);
+        assert_eq!(name, "app");
+    }
+
+    #[tokio::test]
+    async fn missing_values_are_a_server_error() {
+        let mut parts = parts_with(Extensions::new()).await;
+
+        let rejection = Shared::<u32>::from_request_parts(&mut parts, &())
+            .await
+            .unwrap_err();
+        let response = rejection.into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -1,8 +1,71 @@
 //! Static file serving utilities
 
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, LazyLock, RwLock};
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+
+use bytes::Bytes;
+use http::header::{
+    ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_TYPE, ETAG, HeaderValue,
+    IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, VARY,
+};
+use http::{Request, Response, StatusCode, Uri};
+use http_body_util::{Either, Full};
+use sha2::{Digest, Sha256};
 use tower_http::services::ServeDir;
+use tower_http::services::fs::ServeFileSystemResponseBody;
+use tower_service::Service;
 use uncovr::prelude::ApiRouter;
-use uncovr::routing::get_service;
+use uncovr::routing::{MethodRouter, get_service};
+
+use crate::app::{StaticConfig, StaticOptions};
+
+#[cfg(feature = "compression")]
+use std::io::Write;
+
+/// Cache-Control for files that never change under the same name
+pub const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// Glob matching fingerprinted file names such as `app.3f2a1c9b.css`, e.g.
+/// for a [`CacheRule`](crate::app::CacheRule) marking files hashed by a build
+/// tool as [`IMMUTABLE`]
+pub const FINGERPRINTED: &str =
+    "*.[0-9a-f][0-9a-f][0-9a-f][0-9a-f][0-9a-f][0-9a-f][0-9a-f][0-9a-f].*";
+
+/// Files larger than this get an ETag from their size and modification
+/// time instead of their content
+const MAX_HASHED_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Most ETags kept in [`ETAGS`]
+const MAX_ETAGS: usize = 4096;
+
+/// ETags of served files, keyed by path and invalidated when the file changes
+static ETAGS: LazyLock<RwLock<HashMap<PathBuf, (SystemTime, HeaderValue)>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Extensions worth compressing
+#[cfg(feature = "compression")]
+const COMPRESSIBLE_EXTENSIONS: [&str; 11] = [
+    "html", "htm", "css", "js", "mjs", "json", "svg", "xml", "txt", "map", "wasm",
+];
+
+/// Files smaller than this are served as they are, compressing them gains
+/// next to nothing
+#[cfg(feature = "compression")]
+const MIN_COMPRESS_BYTES: u64 = 1024;
+
+/// Compresses a file's content
+#[cfg(feature = "compression")]
+type Compress = fn(&[u8]) -> std::io::Result<Vec<u8>>;
+
+/// Encodings served for compressed files, by file extension
+#[cfg(feature = "compression")]
+const ENCODINGS: [(&str, Compress); 2] = [("br", compress_br), ("gz", compress_gzip)];
 
 /// Add static file routes to the server.
 ///
@@ -13,13 +76,14 @@ use uncovr::routing::get_service;
 /// let router = add_static_routes();
 /// ```
 pub fn add_static_routes() -> ApiRouter {
-    let public = ServeDir::new("./public");
-
-    ApiRouter::new().nest_service("/public", get_service(public))
+    serve_dir("/public", "./public")
 }
 
 /// Add custom static file route.
 ///
+/// `.br` and `.gz` siblings are served instead of the file when present,
+/// accepted by the client and not older than the file.
+///
 /// # Example
 /// ```rust
 /// use wenzetu::static_files::serve_dir;
@@ -27,6 +91,779 @@ pub fn add_static_routes() -> ApiRouter {
 /// let router = serve_dir("/assets", "./public/assets");
 /// ```
 pub fn serve_dir(path: &str, directory: &str) -> ApiRouter {
-    let serve = ServeDir::new(directory);
-    ApiRouter::new().nest_service(path, get_service(serve))
+    serve_static(&StaticConfig::new(path, directory))
+}
+
+/// Add a static file route from a [`StaticConfig`].
+///
+/// A config served at `/` answers the requests no other route matches.
+///
+/// # Example
+/// ```rust
+/// use wenzetu::app::StaticConfig;
+/// use wenzetu::static_files::serve_static;
+///
+/// let router = serve_static(&StaticConfig::new("/assets", "./dist").precompressed(false));
+/// ```
+pub fn serve_static(config: &StaticConfig) -> ApiRouter {
+    #[cfg(feature = "embedded-assets")]
+    if let Some(files) = config.embedded {
+        let service = get_service(crate::embedded::EmbeddedService::new(config, files));
+        return mount(&config.serve_path, service);
+    }
+    mount(&config.serve_path, get_service(StaticService::new(config)))
+}
+
+/// Nest a static service, or use it as the fallback when served at the root
+fn mount(serve_path: &str, service: MethodRouter) -> ApiRouter {
+    if serve_path.trim_end_matches('/').is_empty() {
+        ApiRouter::new().fallback_service(service)
+    } else {
+        ApiRouter::new().nest_service(serve_path, service)
+    }
+}
+
+/// Static file service adding cache headers and ETags to [`ServeDir`]
+#[derive(Clone)]
+pub struct StaticService {
+    inner: ServeDir,
+    /// Whether `inner` serves `.br`/`.gz` siblings
+    precompressed: bool,
+    /// Compressed copies created on request, see [`CompressedCache`]
+    #[cfg(feature = "compression")]
+    compressed: Option<CompressedCache>,
+    serve_path: String,
+    directory: PathBuf,
+    cache: CachePolicy,
+    spa: Option<SpaFallback>,
+    options: StaticOptions,
+    #[cfg(feature = "images")]
+    images: Option<crate::images::ImageOptions>,
+    etag: bool,
+    last_modified: bool,
+}
+
+/// Body of a [`StaticService`] response, a file or a directory listing
+pub type StaticBody = Either<ServeFileSystemResponseBody, Full<Bytes>>;
+
+impl StaticService {
+    /// Create a service serving files as configured by `config`.
+    ///
+    /// Invalid rules are reported and skipped.
+    pub fn new(config: &StaticConfig) -> Self {
+        #[cfg(feature = "compression")]
+        let compressed = config.compress.then(|| CompressedCache::new(config));
+        #[cfg(feature = "compression")]
+        let precompressed = config.precompressed && compressed.is_none();
+        #[cfg(not(feature = "compression"))]
+        let precompressed = config.precompressed;
+
+        let mut inner = ServeDir::new(&config.directory);
+        if precompressed {
+            // Negotiated through `Accept-Encoding`, falling back to the file itself
+            inner = inner.precompressed_br().precompressed_gzip();
+        }
+
+        Self {
+            inner,
+            precompressed,
+            #[cfg(feature = "compression")]
+            compressed,
+            serve_path: config.serve_path.clone(),
+            directory: PathBuf::from(&config.directory),
+            cache: CachePolicy::new(config),
+            spa: SpaFallback::new(config),
+            options: config.options.clone(),
+            #[cfg(feature = "images")]
+            images: config.images.clone(),
+            etag: config.etag,
+            last_modified: config.last_modified,
+        }
+    }
+
+    /// Whether the options deny a path relative to the static directory
+    async fn is_denied(&self, relative: &Path, is_dir: bool) -> bool {
+        let allowed = if is_dir {
+            self.options.allows_dir(relative)
+        } else {
+            self.options.allows(relative)
+        };
+        if !allowed {
+            return true;
+        }
+        if self.options.follow_symlinks {
+            escapes(&self.directory, relative).await
+        } else {
+            has_symlink(&self.directory, relative).await
+        }
+    }
+
+    /// HTML listing of a directory, skipping entries the options deny
+    async fn autoindex(&self, relative: &Path) -> Response<StaticBody> {
+        let mut entries: Vec<(String, bool)> = Vec::new();
+        if let Ok(mut dir) = tokio::fs::read_dir(self.directory.join(relative)).await {
+            while let Ok(Some(entry)) = dir.next_entry().await {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let is_dir = is_dir(&entry.path()).await;
+                if !self.is_denied(&relative.join(&name), is_dir).await {
+                    entries.push((name, is_dir));
+                }
+            }
+        }
+        // Directories first, then by name
+        entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        let title = html_escape::encode_text(&self.url(relative)).into_owned();
+        let mut html = format!(
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Index of {0}/</title>\
+             </head><body><h1>Index of {0}/</h1><ul>",
+            title
+        );
+        if let Some(parent) = relative.parent() {
+            html.push_str(&format!(
+                "<li><a href=\"{}/\">../</a></li>",
+                html_escape::encode_double_quoted_attribute(&self.url(parent))
+            ));
+        }
+        for (name, is_dir) in entries {
+            let slash = if is_dir { "/" } else { "" };
+            html.push_str(&format!(
+                "<li><a href=\"{}{}\">{}{}</a></li>",
+                html_escape::encode_double_quoted_attribute(&self.url(&relative.join(&name))),
+                slash,
+                html_escape::encode_text(&name),
+                slash
+            ));
+        }
+        html.push_str("</ul></body></html>");
+
+        let mut response = Response::new(Either::Right(Full::new(Bytes::from(html))));
+        response.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/html; charset=utf-8"),
+        );
+        response
+            .headers_mut()
+            .insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        response
+    }
+
+    /// URL of a path relative to the static directory
+    fn url(&self, relative: &Path) -> String {
+        let mut url = self.serve_path.trim_end_matches('/').to_string();
+        for component in relative.components() {
+            let part = component.as_os_str().to_string_lossy();
+            url.push('/');
+            url.extend(percent_encoding::utf8_percent_encode(&part, PATH));
+        }
+        url
+    }
+}
+
+/// `index.html` fallback of a single-page app mount
+#[derive(Clone)]
+pub(crate) struct SpaFallback {
+    serve_path: String,
+    exclude: Arc<Vec<String>>,
+}
+
+impl SpaFallback {
+    /// The fallback of `config`, `None` unless `StaticConfig::spa` is set
+    pub(crate) fn new(config: &StaticConfig) -> Option<Self> {
+        config.spa.then(|| Self {
+            serve_path: config.serve_path.trim_end_matches('/').to_string(),
+            exclude: Arc::new(config.spa_exclude.clone()),
+        })
+    }
+
+    /// Whether a missing file gets `index.html`. Paths with an extension are
+    /// missing assets rather than client-side routes.
+    pub(crate) fn applies(&self, relative: &Path) -> bool {
+        if relative.extension().is_some() {
+            return false;
+        }
+        let path = format!(
+            "{}/{}",
+            self.serve_path,
+            relative.to_string_lossy().replace('\\', "/")
+        );
+        !self.exclude.iter().any(|prefix| {
+            let prefix = prefix.trim_end_matches('/');
+            path.trim_end_matches('/') == prefix || path.starts_with(&format!("{}/", prefix))
+        })
+    }
+}
+
+/// Cache-Control rules of a static mount
+#[derive(Clone)]
+pub(crate) struct CachePolicy {
+    rules: Arc<Vec<(glob::Pattern, HeaderValue)>>,
+    default: Option<HeaderValue>,
+}
+
+impl CachePolicy {
+    /// Parse the rules of `config`, invalid rules are reported and skipped
+    pub(crate) fn new(config: &StaticConfig) -> Self {
+        let rules = config
+            .cache_rules
+            .iter()
+            .filter_map(|rule| {
+                let parsed = glob::Pattern::new(&rule.pattern)
+                    .map_err(|err| err.to_string())
+                    .and_then(|pattern| {
+                        let value =
+                            HeaderValue::from_str(&rule.value).map_err(|err| err.to_string())?;
+                        Ok((pattern, value))
+                    });
+                parsed
+                    .inspect_err(|err| eprintln!("Invalid cache rule '{}': {}", rule.pattern, err))
+                    .ok()
+            })
+            .collect();
+
+        Self {
+            rules: Arc::new(rules),
+            default: HeaderValue::from_str(&config.default_cache_control).ok(),
+        }
+    }
+
+    /// Cache-Control for a path relative to the static directory
+    pub(crate) fn get(&self, relative: &Path) -> Option<HeaderValue> {
+        let file_name = relative.file_name().map(Path::new);
+        self.rules
+            .iter()
+            .find(|(pattern, _)| {
+                pattern.matches_path(relative)
+                    || file_name.is_some_and(|name| pattern.matches_path(name))
+            })
+            .map(|(_, value)| value.clone())
+            .or_else(|| self.default.clone())
+    }
+}
+
+impl<B> Service<Request<B>> for StaticService
+where
+    B: Send + 'static,
+{
+    type Response = Response<StaticBody>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Service::<Request<B>>::poll_ready(&mut self.inner, cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let response = self.clone().respond(req);
+        Box::pin(async move { Ok(crate::error_pages::mark(response.await?)) })
+    }
+}
+
+impl StaticService {
+    async fn respond<B>(mut self, mut req: Request<B>) -> Result<Response<StaticBody>, Infallible>
+    where
+        B: Send + 'static,
+    {
+        let requested = request_path(req.uri().path());
+
+        #[cfg(feature = "images")]
+        if let Some(images) = &self.images
+            && let Some(image) = crate::images::parse_request(&requested, req.uri().query())
+        {
+            return Ok(match image {
+                Ok((transform, source)) if !self.is_denied(&source, false).await => {
+                    crate::images::respond(
+                        images.clone(),
+                        self.directory.clone(),
+                        source,
+                        transform,
+                        self.cache.get(&requested),
+                        req.headers().get(IF_NONE_MATCH).cloned(),
+                    )
+                    .await
+                }
+                Ok(_) => not_found(),
+                Err(err) => {
+                    eprintln!("Invalid image request: {}", err);
+                    let mut response = not_found();
+                    *response.status_mut() = StatusCode::BAD_REQUEST;
+                    response
+                }
+            });
+        }
+
+        let original = crate::assets::original_path(&self.serve_path, &requested.to_string_lossy());
+        let mut relative = original.as_ref().map_or(requested.clone(), PathBuf::from);
+
+        if let Some(original) = &original {
+            let path = percent_encoding::utf8_percent_encode(original, PATH);
+            let uri = match req.uri().query() {
+                Some(query) => format!("/{}?{}", path, query),
+                None => format!("/{}", path),
+            };
+            if let Ok(uri) = uri.parse() {
+                *req.uri_mut() = uri;
+            }
+        }
+        // Decided before `index.html` is appended, so directories without
+        // one get the app too
+        let spa = original.is_none() && self.spa.as_ref().is_some_and(|spa| spa.applies(&relative));
+        if is_dir(&self.directory.join(&relative)).await {
+            let listed = self.options.autoindex
+                && !is_file(&self.directory.join(&relative).join("index.html")).await
+                && !self.is_denied(&relative, true).await;
+            if listed {
+                return Ok(self.autoindex(&relative).await);
+            }
+            relative.push("index.html");
+        }
+        if spa && !is_file(&self.directory.join(&relative)).await {
+            relative = PathBuf::from("index.html");
+            *req.uri_mut() = Uri::from_static("/index.html");
+        }
+        if self.is_denied(&relative, false).await {
+            return Ok(not_found());
+        }
+        // Names from the manifest change with the content
+        let cache_control = if original.is_some() {
+            Some(HeaderValue::from_static(IMMUTABLE))
+        } else {
+            self.cache.get(&relative)
+        };
+
+        let if_none_match = if self.etag {
+            req.headers_mut().remove(IF_NONE_MATCH)
+        } else {
+            None
+        };
+        // `If-None-Match` takes precedence over `If-Modified-Since`
+        if if_none_match.is_some() || !self.last_modified {
+            req.headers_mut().remove(IF_MODIFIED_SINCE);
+        }
+
+        let path = self.directory.join(&relative);
+        let accepts_encoding = req.headers().contains_key(ACCEPT_ENCODING);
+
+        #[cfg(feature = "compression")]
+        let compressed = match &self.compressed {
+            Some(cache) if accepts_encoding => cache.refresh(&path, &relative).await,
+            _ => false,
+        };
+
+        // Siblings left from before the file was edited would serve
+        // the old content
+        if self.precompressed && accepts_encoding && has_stale_sibling(&path).await {
+            req.headers_mut().remove(ACCEPT_ENCODING);
+        }
+
+        #[cfg(feature = "compression")]
+        let mut response = match &mut self.compressed {
+            Some(cache) if compressed => cache.serve.call(req).await?,
+            _ => self.inner.call(req).await?,
+        };
+        #[cfg(not(feature = "compression"))]
+        let mut response = self.inner.call(req).await?;
+        let status = response.status();
+        if !status.is_success() && status != StatusCode::NOT_MODIFIED {
+            return Ok(response.map(Either::Left));
+        }
+
+        if let Some(value) = cache_control {
+            response.headers_mut().insert(CACHE_CONTROL, value);
+        }
+        if !self.last_modified {
+            response.headers_mut().remove(LAST_MODIFIED);
+        }
+        if !self.etag || !status.is_success() {
+            return Ok(response.map(Either::Left));
+        }
+
+        // Each encoding is a different representation with its own ETag,
+        // computed from the file that was served
+        let extension = match response
+            .headers()
+            .get(CONTENT_ENCODING)
+            .map(|v| v.as_bytes())
+        {
+            Some(b"br") => Some(".br"),
+            Some(b"gzip") => Some(".gz"),
+            _ => None,
+        };
+        let mut path = match extension {
+            // Without an encoding the cache fell back to the original file
+            #[cfg(feature = "compression")]
+            Some(_) if compressed => self
+                .compressed
+                .as_ref()
+                .map_or(path, |cache| cache.directory.join(&relative)),
+            _ => path,
+        };
+        if let Some(extension) = extension {
+            path.as_mut_os_string().push(extension);
+        }
+        let Some(etag) = file_etag(&path).await else {
+            return Ok(response.map(Either::Left));
+        };
+
+        if if_none_match.is_some_and(|value| etag_matches(&value, &etag)) {
+            let mut not_modified = Response::new(ServeFileSystemResponseBody::default());
+            *not_modified.status_mut() = StatusCode::NOT_MODIFIED;
+            for name in [CACHE_CONTROL, LAST_MODIFIED, VARY] {
+                if let Some(value) = response.headers().get(&name) {
+                    not_modified.headers_mut().insert(name, value.clone());
+                }
+            }
+            not_modified.headers_mut().insert(ETAG, etag);
+            return Ok(not_modified.map(Either::Left));
+        }
+
+        response.headers_mut().insert(ETAG, etag);
+        Ok(response.map(Either::Left))
+    }
+}
+
+fn not_found() -> Response<StaticBody> {
+    let mut response = Response::new(Either::Left(ServeFileSystemResponseBody::default()));
+    *response.status_mut() = StatusCode::NOT_FOUND;
+    response
+}
+
+/// Characters escaped when rewriting a request to the original file
+const PATH: &percent_encoding::AsciiSet = &percent_encoding::CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Decode a request path into a path relative to the static directory
+pub(crate) fn request_path(path: &str) -> PathBuf {
+    let decoded = percent_encoding::percent_decode_str(path).decode_utf8_lossy();
+    Path::new(decoded.as_ref())
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether a path relative to `directory`, or one of its parents inside
+/// `directory`, is a symlink
+async fn has_symlink(directory: &Path, relative: &Path) -> bool {
+    let mut path = directory.to_path_buf();
+    for component in relative.components() {
+        path.push(component);
+        if tokio::fs::symlink_metadata(&path)
+            .await
+            .is_ok_and(|meta| meta.file_type().is_symlink())
+        {
+            return true;
+        }
+    }
+    false
+}
+
+/// Whether a path relative to `directory` resolves outside of it through a
+/// symlink
+async fn escapes(directory: &Path, relative: &Path) -> bool {
+    let root = tokio::fs::canonicalize(directory).await;
+    let target = tokio::fs::canonicalize(directory.join(relative)).await;
+    match (root, target) {
+        (Ok(root), Ok(target)) => !target.starts_with(root),
+        // Missing files are answered with `404` anyway
+        _ => false,
+    }
+}
+
+async fn is_dir(path: &Path) -> bool {
+    tokio::fs::metadata(path)
+        .await
+        .is_ok_and(|meta| meta.is_dir())
+}
+
+async fn is_file(path: &Path) -> bool {
+    tokio::fs::metadata(path)
+        .await
+        .is_ok_and(|meta| meta.is_file())
+}
+
+/// Whether a `.br` or `.gz` sibling of `path` is older than the file
+async fn has_stale_sibling(path: &Path) -> bool {
+    let modified = |path: PathBuf| async move {
+        tokio::fs::metadata(path)
+            .await
+            .and_then(|meta| meta.modified())
+            .ok()
+    };
+    let Some(source) = modified(path.to_path_buf()).await else {
+        return false;
+    };
+    for extension in ["br", "gz"] {
+        let mut sibling = path.as_os_str().to_owned();
+        sibling.push(".");
+        sibling.push(extension);
+        if modified(PathBuf::from(sibling))
+            .await
+            .is_some_and(|sibling| sibling < source)
+        {
+            return true;
+        }
+    }
+    false
+}
+
+/// Strong ETag of a file, reused until the file is modified.
+///
+/// Files up to [`MAX_HASHED_BYTES`] are hashed while read in chunks, larger
+/// ones get an ETag from their size and modification time.
+async fn file_etag(path: &Path) -> Option<HeaderValue> {
+    let meta = tokio::fs::metadata(path).await.ok()?;
+    let modified = meta.modified().ok()?;
+
+    if let Ok(etags) = ETAGS.read()
+        && let Some((cached_at, etag)) = etags.get(path)
+        && *cached_at == modified
+    {
+        return Some(etag.clone());
+    }
+
+    let tag = if meta.len() > MAX_HASHED_BYTES {
+        let since_epoch = modified.duration_since(SystemTime::UNIX_EPOCH).ok()?;
+        format!("{:x}-{:x}", meta.len(), since_epoch.as_nanos())
+    } else {
+        let file = path.to_path_buf();
+        tokio::task::spawn_blocking(move || hash_file(&file))
+            .await
+            .ok()?
+            .ok()?
+    };
+    let etag = HeaderValue::from_str(&format!("\"{}\"", tag)).ok()?;
+
+    if let Ok(mut etags) = ETAGS.write() {
+        // Forget an arbitrary entry rather than growing without bound
+        if etags.len() >= MAX_ETAGS
+            && let Some(evicted) = etags.keys().next().cloned()
+        {
+            etags.remove(&evicted);
+        }
+        etags.insert(path.to_path_buf(), (modified, etag.clone()));
+    }
+    Some(etag)
+}
+
+/// Same hash as [`content_hash`], reading the file in chunks
+fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok(hex(&hasher.finalize()[..16]))
+}
+
+/// Hex encoded SHA-256 of `content`, shortened to 16 bytes
+pub(crate) fn content_hash(content: &[u8]) -> String {
+    hex(&Sha256::digest(content)[..16])
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Whether an `If-None-Match` header matches `etag`, using the weak
+/// comparison required for `If-None-Match`
+pub(crate) fn etag_matches(if_none_match: &HeaderValue, etag: &HeaderValue) -> bool {
+    let Ok(if_none_match) = if_none_match.to_str() else {
+        return false;
+    };
+    let etag = etag.to_str().unwrap_or_default();
+    if_none_match.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+    })
+}
+
+/// Compressed copies of the files of a static mount, created on the first
+/// request accepting an encoding and kept outside the static directory.
+///
+/// Copies carry the modification time of the file they were made from and
+/// are created again as soon as it differs, so edits show up immediately.
+#[cfg(feature = "compression")]
+#[derive(Clone)]
+struct CompressedCache {
+    /// Where the copies are kept, mirroring the static directory
+    directory: PathBuf,
+    /// Serves a copy negotiated through `Accept-Encoding`, falling back to
+    /// the static directory for files without one
+    serve: ServeDir<ServeDir>,
+}
+
+#[cfg(feature = "compression")]
+impl CompressedCache {
+    fn new(config: &StaticConfig) -> Self {
+        let directory = Path::new(&config.compress_cache_dir)
+            .join(config.serve_path.trim_matches('/').replace('/', "_"));
+        let serve = ServeDir::new(&directory)
+            .precompressed_br()
+            .precompressed_gzip()
+            .fallback(ServeDir::new(&config.directory));
+        Self { directory, serve }
+    }
+
+    /// Bring the copies of the file at `path` up to date, returning whether
+    /// it should be served from the cache
+    async fn refresh(&self, path: &Path, relative: &Path) -> bool {
+        let compressible = relative
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| COMPRESSIBLE_EXTENSIONS.contains(&ext));
+        if !compressible {
+            return false;
+        }
+
+        let source = path.to_path_buf();
+        let cached = self.directory.join(relative);
+        let result = tokio::task::spawn_blocking(move || compress_file(&source, &cached)).await;
+        match result {
+            Ok(Ok(())) => true,
+            Ok(Err(err)) if err.kind() == std::io::ErrorKind::NotFound => false,
+            Ok(Err(err)) => {
+                eprintln!("Failed to compress {}: {}", path.display(), err);
+                false
+            }
+            Err(err) => {
+                eprintln!("Failed to compress {}: {}", path.display(), err);
+                false
+            }
+        }
+    }
+}
+
+/// Write `.br` and `.gz` copies of `source` next to `cached`, unless they
+/// were made from the current version of the file
+#[cfg(feature = "compression")]
+fn compress_file(source: &Path, cached: &Path) -> std::io::Result<()> {
+    let meta = std::fs::metadata(source)?;
+    if !meta.is_file() {
+        return Ok(());
+    }
+    let modified = meta.modified()?;
+    let mut content = None;
+
+    for (extension, compress) in ENCODINGS {
+        let copy = sibling_path(cached, extension);
+        let copy_modified = std::fs::metadata(&copy).and_then(|meta| meta.modified());
+        if copy_modified.is_ok_and(|time| time == modified) {
+            continue;
+        }
+        if meta.len() < MIN_COMPRESS_BYTES {
+            // Left from a larger version of the file
+            let _ = std::fs::remove_file(&copy);
+            continue;
+        }
+
+        let content = match &mut content {
+            Some(content) => content,
+            None => content.insert(std::fs::read(source)?),
+        };
+        let compressed = compress(content)?;
+        let parent = copy.parent().unwrap_or(Path::new("."));
+        std::fs::create_dir_all(parent)?;
+        // Renamed into place so concurrent requests never serve a partial
+        // copy, with the time of the version it was made from
+        let mut file = tempfile::NamedTempFile::new_in(parent)?;
+        file.write_all(&compressed)?;
+        file.as_file().set_modified(modified)?;
+        file.persist(&copy).map_err(|err| err.error)?;
+    }
+    Ok(())
+}
+
+/// Write `.br` and `.gz` siblings for every compressible file in `directory`,
+/// e.g. in a build step for [`StaticConfig::precompressed`].
+///
+/// Siblings newer than their source are kept, so only new or changed files
+/// are compressed again. Returns the number of files written.
+///
+/// # Example
+/// ```rust,no_run
+/// use wenzetu::static_files::compress_dir;
+///
+/// let written = compress_dir("./public").unwrap();
+/// println!("Compressed {} files", written);
+/// ```
+#[cfg(feature = "compression")]
+pub fn compress_dir(directory: impl AsRef<Path>) -> std::io::Result<usize> {
+    let mut written = 0;
+    let mut pending = vec![directory.as_ref().to_path_buf()];
+
+    while let Some(path) = pending.pop() {
+        if path.is_dir() {
+            for entry in std::fs::read_dir(&path)? {
+                pending.push(entry?.path());
+            }
+            continue;
+        }
+
+        let compressible = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| COMPRESSIBLE_EXTENSIONS.contains(&ext));
+        if !compressible {
+            continue;
+        }
+
+        for (extension, compress) in ENCODINGS {
+            let sibling = sibling_path(&path, extension);
+            if is_up_to_date(&path, &sibling) {
+                continue;
+            }
+
+            let source = std::fs::read(&path)?;
+            let compressed = compress(&source)?;
+            // Not worth serving if it doesn't get smaller
+            if compressed.len() < source.len() {
+                std::fs::write(&sibling, compressed)?;
+                written += 1;
+            }
+        }
+    }
+
+    Ok(written)
+}
+
+/// `app.css` -> `app.css.br`
+#[cfg(feature = "compression")]
+fn sibling_path(path: &Path, extension: &str) -> PathBuf {
+    let mut sibling = path.as_os_str().to_owned();
+    sibling.push(".");
+    sibling.push(extension);
+    PathBuf::from(sibling)
+}
+
+/// Whether `sibling` exists and is newer than `source`
+#[cfg(feature = "compression")]
+fn is_up_to_date(source: &Path, sibling: &Path) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|meta| meta.modified());
+    match (modified(source), modified(sibling)) {
+        (Ok(source), Ok(sibling)) => sibling >= source,
+        _ => false,
+    }
+}
+
+#[cfg(feature = "compression")]
+fn compress_br(source: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+    encoder.write_all(source)?;
+    // Finishes the stream
+    Ok(encoder.into_inner())
+}
+
+#[cfg(feature = "compression")]
+fn compress_gzip(source: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(source)?;
+    encoder.finish()
 }
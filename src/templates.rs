@@ -1,29 +1,41 @@
-//! Template rendering with Tera
+//! Template rendering
 //!
-//! Provides a global Tera instance with hot-reload in development and
-//! comprehensive error handling with detailed debug pages.
+//! Provides a global template engine (Tera by default) with hot-reload in
+//! development and comprehensive error handling with detailed debug pages.
 
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, LazyLock, RwLock};
 use tera::{Context, Tera};
 
-#[cfg(feature = "live-reload")]
-use std::time::Duration;
-#[cfg(feature = "live-reload")]
-use tera_hot_reload::watch;
-#[cfg(feature = "live-reload")]
-use tower_livereload::Reloader;
+use crate::engine::{
+    Engine, TERA_SETUP, TemplateEngine, TeraEngine, TeraSetup, error_location, error_message,
+};
+use crate::error::Error;
 
-/// Live reload reloader (active in development only).
 #[cfg(feature = "live-reload")]
-pub static LIVE_RELOADER: LazyLock<Reloader> = LazyLock::new(Reloader::new);
+pub use crate::live_reload::{LIVE_RELOADER, live_reload_layer};
 
-/// Tracks the latest Tera template initialization or reload error.
+/// Tracks the latest template initialization or reload error.
 pub static TERA_INIT_ERROR: LazyLock<RwLock<Option<String>>> = LazyLock::new(|| RwLock::new(None));
 
+/// The engine error behind [`TERA_INIT_ERROR`], kept as the source of
+/// [`Error::Template`]
+static LOAD_ERROR: RwLock<Option<Arc<dyn std::error::Error + Send + Sync>>> = RwLock::new(None);
+
 /// Template path configuration
 pub static TEMPLATE_PATH: RwLock<String> = RwLock::new(String::new());
 
-/// Initialize templates with a custom path
+/// Template engine configuration
+pub static TEMPLATE_ENGINE: RwLock<Engine> = RwLock::new(Engine::Tera);
+
+/// Whether live reload is enabled for rendered pages
+pub static LIVE_RELOAD_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether [`TEMPLATES`] has been initialized
+static TEMPLATES_LOADED: AtomicBool = AtomicBool::new(false);
+
+/// Initialize templates with a custom path. Once the templates are loaded,
+/// the new path is used from the next [`reload_templates`].
 pub fn init_templates(path: impl Into<String>) {
     let path_str = path.into();
     if let Ok(mut template_path) = TEMPLATE_PATH.write() {
@@ -31,63 +43,137 @@ pub fn init_templates(path: impl Into<String>) {
     }
 }
 
-/// Get the configured template path
-fn get_template_path() -> String {
-    if let Ok(path) = TEMPLATE_PATH.read() {
-        if !path.is_empty() {
-            return path.clone();
-        }
+/// Select the template engine. Once the templates are loaded, the new
+/// engine is used from the next [`reload_templates`].
+pub fn init_engine(engine: Engine) {
+    if let Ok(mut template_engine) = TEMPLATE_ENGINE.write() {
+        *template_engine = engine;
     }
-    "templates/**/*".to_string()
 }
 
-/// Global Tera instance shared across the application.
-pub static TEMPLATES: LazyLock<Arc<RwLock<Tera>>> = LazyLock::new(|| {
-    let template_path = get_template_path();
-    let mut tera = match Tera::new(&template_path) {
-        Ok(t) => t,
-        Err(err) => {
-            if let Ok(mut lock) = TERA_INIT_ERROR.write() {
-                *lock = Some(err.to_string());
-            }
-            Tera::default()
-        }
-    };
+/// Customize the Tera instance, e.g. to register filters, functions or
+/// testers.
+///
+/// `setup` runs every time the templates are loaded, so registrations
+/// survive reloads, and right away when they are already loaded. Has no
+/// effect on the other engines.
+///
+/// # Example
+/// ```rust
+/// use wenzetu::templates::configure_tera;
+///
+/// configure_tera(|tera| {
+///     tera.register_filter("shout", |value: &tera::Value, _: &_| {
+///         Ok(value.as_str().unwrap_or_default().to_uppercase().into())
+///     });
+/// });
+/// ```
+pub fn configure_tera<F>(setup: F)
+where
+    F: Fn(&mut Tera) + Send + Sync + 'static,
+{
+    let setup: TeraSetup = Arc::new(setup);
+    if let Ok(mut setups) = TERA_SETUP.write() {
+        setups.push(setup.clone());
+    }
 
-    // Configure auto-escaping for security
-    tera.autoescape_on(vec![".html", ".htm", ".xml", ".svg"]);
+    // Loading the templates here would ignore a template path set later
+    if TEMPLATES_LOADED.load(Ordering::SeqCst)
+        && let Ok(mut templates) = TEMPLATES.write()
+        && let Some(engine) = templates
+            .as_any_mut()
+            .and_then(|engine| engine.downcast_mut::<TeraEngine>())
+    {
+        setup(engine.tera_mut());
+    }
+}
 
-    let tera_ref = Arc::new(RwLock::new(tera));
+/// Enable or disable live reload for rendered pages.
+///
+/// Has no effect without the `live-reload` feature. Files are watched
+/// separately, see `live_reload::watch`.
+pub fn init_live_reload(enabled: bool) {
+    LIVE_RELOAD_ENABLED.store(enabled, Ordering::SeqCst);
+}
 
-    // Enable live reload during development
-    #[cfg(feature = "live-reload")]
+/// Get the configured template path
+pub(crate) fn get_template_path() -> String {
+    if let Ok(path) = TEMPLATE_PATH.read()
+        && !path.is_empty()
     {
-        let watch_ref = Arc::clone(&tera_ref);
-        let watch_path = get_template_path().replace("/**/*", "");
-        let _debouncer = watch(
-            move || {
-                if let Ok(mut tera_guard) = watch_ref.write() {
-                    if let Err(err) = tera_guard.full_reload() {
-                        if let Ok(mut lock) = TERA_INIT_ERROR.write() {
-                            *lock = Some(err.to_string());
-                        }
-                    } else {
-                        if let Ok(mut lock) = TERA_INIT_ERROR.write() {
-                            *lock = None;
-                        }
-                    }
-                }
-                LIVE_RELOADER.reload();
-            },
-            Duration::from_millis(100),
-            vec![watch_path],
-        );
-        std::mem::forget(_debouncer);
+        return path.clone();
     }
+    "templates/**/*".to_string()
+}
 
-    tera_ref
+/// Reload templates and record the outcome in [`TERA_INIT_ERROR`]
+fn reload_engine(engine: &mut dyn TemplateEngine) -> Result<(), String> {
+    let error: Option<Arc<dyn std::error::Error + Send + Sync>> =
+        engine.reload().err().map(Arc::from);
+    let message = error.as_deref().map(|err| error_message(err));
+    if let Ok(mut lock) = TERA_INIT_ERROR.write() {
+        *lock = message.clone();
+    }
+    if let Ok(mut lock) = LOAD_ERROR.write() {
+        *lock = error;
+    }
+    message.map_or(Ok(()), Err)
+}
+
+/// Global template engine shared across the application.
+pub static TEMPLATES: LazyLock<Arc<RwLock<Box<dyn TemplateEngine>>>> = LazyLock::new(|| {
+    let template_path = get_template_path();
+    let engine = TEMPLATE_ENGINE.read().map(|e| *e).unwrap_or_default();
+
+    let mut templates = engine.create(&template_path);
+    // Errors are kept in TERA_INIT_ERROR and shown when rendering
+    let _ = reload_engine(templates.as_mut());
+    TEMPLATES_LOADED.store(true, Ordering::SeqCst);
+
+    Arc::new(RwLock::new(templates))
 });
 
+/// Reload all templates from disk, e.g. after a file change, picking up a
+/// path or engine set since they were loaded.
+///
+/// On error the previous templates are kept and the error is also recorded
+/// in [`TERA_INIT_ERROR`].
+pub fn reload_templates() -> Result<(), String> {
+    LazyLock::force(&TEMPLATES);
+    let engine = TEMPLATE_ENGINE.read().map(|e| *e).unwrap_or_default();
+    let mut templates = engine.create(&get_template_path());
+    reload_engine(templates.as_mut())?;
+
+    let mut templates_guard = TEMPLATES.write().map_err(|err| err.to_string())?;
+    *templates_guard = templates;
+    Ok(())
+}
+
+/// Whether the templates have been loaded, after which
+/// [`init_templates`] and [`init_engine`] take effect on
+/// [`reload_templates`]
+pub(crate) fn templates_loaded() -> bool {
+    TEMPLATES_LOADED.load(Ordering::SeqCst)
+}
+
+/// Load the templates if they aren't loaded yet and return the load error,
+/// if any.
+///
+/// `render` shows load errors as an error page instead, which is what
+/// development wants; call this at startup to fail early otherwise.
+pub fn check_templates() -> Result<(), Error> {
+    LazyLock::force(&TEMPLATES);
+    let Some(message) = TERA_INIT_ERROR.read().ok().and_then(|lock| lock.clone()) else {
+        return Ok(());
+    };
+    let source = LOAD_ERROR.read().ok().and_then(|lock| lock.clone());
+    Err(Error::Template {
+        name: error_location(&message).0,
+        message,
+        source: source.map(|err| Box::new(err) as _),
+    })
+}
+
 /// Render a template with the given context.
 ///
 /// # Example
@@ -101,28 +187,30 @@ pub static TEMPLATES: LazyLock<Arc<RwLock<Tera>>> = LazyLock::new(|| {
 /// ```
 pub fn render(name: &str, context: &Context) -> String {
     match TEMPLATES.read() {
-        Ok(tera_guard) => {
+        Ok(templates_guard) => {
             // Check for initialization errors
-            if let Ok(err_lock) = TERA_INIT_ERROR.read() {
-                if let Some(init_err) = err_lock.as_ref() {
-                    eprintln!("Template initialization error: {}", init_err);
-                    return format!(
-                        "<!DOCTYPE html><html><body><h1>Template Error</h1><pre>{}</pre></body></html>",
-                        html_escape::encode_text(init_err)
-                    );
-                }
+            if let Ok(err_lock) = TERA_INIT_ERROR.read()
+                && let Some(init_err) = err_lock.as_ref()
+            {
+                eprintln!("Template initialization error: {}", init_err);
+                return format!(
+                    "<!DOCTYPE html><html><body><h1>Template Error</h1><pre>{}</pre></body></html>",
+                    html_escape::encode_text(init_err)
+                );
             }
 
-            match tera_guard.render(name, context) {
+            match templates_guard.render(name, context) {
+                #[cfg(feature = "live-reload")]
+                Ok(html)
+                    if LIVE_RELOAD_ENABLED.load(Ordering::SeqCst)
+                        && (name.ends_with(".html") || name.ends_with(".htm")) =>
+                {
+                    crate::live_reload::inject_client(html)
+                }
                 Ok(html) => html,
                 Err(err) => {
                     eprintln!("Template render error in '{}': {}", name, err);
-                    format!(
-                        "<!DOCTYPE html><html><body><h1>Template Render Error</h1>\
-                        <p>Template: {}</p><pre>{}</pre></body></html>",
-                        html_escape::encode_text(name),
-                        html_escape::encode_text(&err.to_string())
-                    )
+                    render_error_page(name, &err)
                 }
             }
         }
@@ -136,31 +224,172 @@ pub fn render(name: &str, context: &Context) -> String {
     }
 }
 
+/// Render a template, returning errors instead of an error page.
+///
+/// Use this where an HTML error page makes no sense, e.g. emails.
+pub fn try_render(name: &str, context: &Context) -> Result<String, String> {
+    // Loads the templates first, which records the load error
+    let templates_guard = TEMPLATES.read().map_err(|err| err.to_string())?;
+
+    if let Ok(err_lock) = TERA_INIT_ERROR.read()
+        && let Some(init_err) = err_lock.as_ref()
+    {
+        return Err(init_err.clone());
+    }
+
+    templates_guard.render(name, context)
+}
+
+/// Handle to the global template engine, available to handlers as
+/// `Shared<Templates>`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Templates;
+
+impl Templates {
+    /// Render a template, see [`render`]
+    pub fn render(&self, name: &str, context: &Context) -> String {
+        render(name, context)
+    }
+
+    /// Render a template, returning errors instead of an error page, see
+    /// [`try_render`]
+    pub fn try_render(&self, name: &str, context: &Context) -> Result<String, String> {
+        try_render(name, context)
+    }
+
+    /// Whether a template with this name is loaded
+    pub fn has_template(&self, name: &str) -> bool {
+        TEMPLATES
+            .read()
+            .is_ok_and(|templates| templates.has_template(name))
+    }
+}
+
+/// Error page shown when a template fails to render
+pub(crate) fn render_error_page(name: &str, err: &str) -> String {
+    format!(
+        "<!DOCTYPE html><html><body><h1>Template Render Error</h1>\
+        <p>Template: {}</p><pre>{}</pre></body></html>",
+        html_escape::encode_text(name),
+        html_escape::encode_text(err)
+    )
+}
+
 /// Create a Tera context from key-value pairs.
 ///
+/// Keys can be identifiers or string literals (for keys such as
+/// `"page-title"` that are not valid Rust identifiers). A `#{ ... }` value
+/// builds a nested map, `..value` merges the fields of any `Serialize`
+/// struct or map, and `if cond => ...` only inserts its entries when `cond`
+/// is true. Any other value is an expression, including `{ ... }` blocks.
+///
 /// # Example
 /// ```rust
 /// use wenzetu::context;
 ///
+/// #[derive(serde::Serialize)]
+/// struct Seo {
+///     description: String,
+/// }
+///
+/// let seo = Seo { description: "A page".to_string() };
+/// let logged_in = true;
+///
 /// let ctx = context! {
 ///     name: "World",
 ///     count: 42,
+///     "page-title": "Home",
+///     user: #{
+///         id: 7,
+///         role: "admin",
+///     },
+///     total: {
+///         let prices = [3, 4];
+///         prices.iter().sum::<i32>()
+///     },
+///     ..seo,
+///     if logged_in => greeting: "Welcome back",
+///     if !logged_in => {
+///         login_url: "/login",
+///     },
 /// };
+///
+/// assert!(ctx.contains_key("page-title"));
+/// assert_eq!(ctx.get("total"), Some(&7.into()));
+/// assert!(ctx.contains_key("description"));
+/// assert!(ctx.contains_key("greeting"));
+/// assert!(!ctx.contains_key("login_url"));
 /// ```
+///
+/// # Panics
+///
+/// Spreading a value that does not serialize to a map (e.g. a number or a
+/// list) panics, since it has no fields to merge.
 #[macro_export]
 macro_rules! context {
-    ($($key:ident: $value:expr),* $(,)?) => {{
-        let mut ctx = tera::Context::new();
-        $(
-            ctx.insert(stringify!($key), &$value);
-        )*
-        ctx
-    }};
+    ($($entries:tt)*) => {
+        $crate::templates::__context!($crate; $($entries)*)
+    };
 }
 
-/// Re-export the live reload layer for easy integration
-#[cfg(feature = "live-reload")]
-pub fn live_reload_layer() -> tower_livereload::LiveReloadLayer {
-    use tower_livereload::LiveReloadLayer;
-    LiveReloadLayer::new()
+/// Parses the entries of [`context!`]
+#[doc(hidden)]
+pub use wenzetu_macros::context as __context;
+
+/// Load the templates under `tests/templates`, shared by every test in the
+/// crate since the engine is global
+#[cfg(test)]
+pub(crate) fn load_test_templates() {
+    init_templates("tests/templates/**/*");
+    reload_templates().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tera_setup_survives_reloads() {
+        load_test_templates();
+        configure_tera(|tera| {
+            tera.register_filter("shout", |value: &tera::Value, _: &_| {
+                Ok(value.as_str().unwrap_or_default().to_uppercase().into())
+            });
+        });
+
+        let context = crate::context! { name: "Ada" };
+        assert_eq!(try_render("shout.txt", &context).unwrap(), "ADA");
+
+        reload_templates().unwrap();
+        assert_eq!(try_render("shout.txt", &context).unwrap(), "ADA");
+    }
+
+    #[test]
+    fn large_contexts_compile() {
+        let ctx = "outer";
+        let context = crate::context! {
+            k0: 0, k1: 1, k2: 2, k3: 3, k4: 4, k5: 5, k6: 6, k7: 7, k8: 8, k9: 9,
+            k10: 10, k11: 11, k12: 12, k13: 13, k14: 14, k15: 15, k16: 16, k17: 17, k18: 18, k19: 19,
+            k20: 20, k21: 21, k22: 22, k23: 23, k24: 24, k25: 25, k26: 26, k27: 27, k28: 28, k29: 29,
+            k30: 30, k31: 31, k32: 32, k33: 33, k34: 34, k35: 35, k36: 36, k37: 37, k38: 38, k39: 39,
+            k40: 40, k41: 41, k42: 42, k43: 43, k44: 44, k45: 45, k46: 46, k47: 47, k48: 48, k49: 49,
+            k50: 50, k51: 51, k52: 52, k53: 53, k54: 54, k55: 55, k56: 56, k57: 57, k58: 58, k59: 59,
+            k60: 60, k61: 61, k62: 62, k63: 63, k64: 64, k65: 65, k66: 66, k67: 67, k68: 68, k69: 69,
+            k70: 70, k71: 71, k72: 72, k73: 73, k74: 74, k75: 75, k76: 76, k77: 77, k78: 78, k79: 79,
+            k80: 80, k81: 81, k82: 82, k83: 83, k84: 84, k85: 85, k86: 86, k87: 87, k88: 88, k89: 89,
+            k90: 90, k91: 91, k92: 92, k93: 93, k94: 94, k95: 95, k96: 96, k97: 97, k98: 98, k99: 99,
+            k100: 100, k101: 101, k102: 102, k103: 103, k104: 104, k105: 105, k106: 106, k107: 107, k108: 108, k109: 109,
+            k110: 110, k111: 111, k112: 112, k113: 113, k114: 114, k115: 115, k116: 116, k117: 117, k118: 118, k119: 119,
+            k120: 120, k121: 121, k122: 122, k123: 123, k124: 124, k125: 125, k126: 126, k127: 127, k128: 128, k129: 129,
+            k130: 130, k131: 131, k132: 132, k133: 133, k134: 134, k135: 135, k136: 136, k137: 137, k138: 138, k139: 139,
+            k140: 140, k141: 141, k142: 142, k143: 143, k144: 144, k145: 145, k146: 146, k147: 147, k148: 148, k149: 149,
+            nested: #{ ctx: ctx },
+            if true => last: 150,
+        };
+
+        let json = context.into_json();
+        assert_eq!(json.as_object().unwrap().len(), 152);
+        assert_eq!(json["k149"], 149);
+        assert_eq!(json["nested"]["ctx"], "outer");
+    }
 }
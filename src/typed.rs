@@ -0,0 +1,333 @@
+//! Typed templates
+//!
+//! Bind a context struct to a template name so handlers can't forget to pass
+//! a variable, and check in tests that a template only uses variables the
+//! struct provides.
+
+use std::collections::HashSet;
+
+use serde::Serialize;
+use tera::ast::{Expr, ExprVal, FunctionCall, Node};
+use tera::{Context, Tera};
+
+use crate::templates;
+
+pub use wenzetu_macros::WenzetuTemplate;
+
+/// A context struct bound to a template.
+///
+/// Usually derived alongside `Serialize`; `#[serde(rename)]`,
+/// `#[serde(skip)]` and the struct's `#[serde(rename_all)]` are taken into
+/// account. `#[serde(flatten)]` fields are rejected, since the derive can't
+/// see the flattened type's fields:
+///
+/// ```rust,compile_fail
+/// # use serde::Serialize;
+/// # use wenzetu::typed::WenzetuTemplate;
+/// # #[derive(Serialize)]
+/// # struct Meta { description: String }
+/// #[derive(Serialize, WenzetuTemplate)]
+/// #[template("index.html")]
+/// struct IndexPage {
+///     title: String,
+///     #[serde(flatten)]
+///     meta: Meta,
+/// }
+/// ```
+///
+/// # Example
+/// ```rust,no_run
+/// use serde::Serialize;
+/// use wenzetu::typed::WenzetuTemplate;
+///
+/// #[derive(Serialize, WenzetuTemplate)]
+/// #[template("index.html")]
+/// struct IndexPage {
+///     title: String,
+///     message: String,
+/// }
+///
+/// let html = IndexPage {
+///     title: "Home".to_string(),
+///     message: "Hello".to_string(),
+/// }
+/// .render();
+/// ```
+pub trait WenzetuTemplate: Serialize {
+    /// Template name, as passed to [`templates::render`]
+    const TEMPLATE: &'static str;
+    /// Top-level variables this struct provides
+    const FIELDS: &'static [&'static str];
+
+    /// Build the Tera context from this struct
+    fn context(&self) -> tera::Result<Context> {
+        Context::from_serialize(self)
+    }
+
+    /// Render the bound template with this struct as context
+    fn render(&self) -> String {
+        match self.context() {
+            Ok(context) => templates::render(Self::TEMPLATE, &context),
+            Err(err) => {
+                eprintln!("Template context error in '{}': {}", Self::TEMPLATE, err);
+                templates::render_error_page(Self::TEMPLATE, &err.to_string())
+            }
+        }
+    }
+}
+
+/// Top-level variables a template reads from its context.
+///
+/// Follows `extends` and `include`, skips variables defined inside the
+/// template (`set`, loop variables) and variables guarded with a `default`
+/// filter or an `is defined` test. Templates are parsed from the configured
+/// template path with Tera's parser, even when another engine renders them:
+/// MiniJinja templates mostly parse, Handlebars templates don't and return
+/// an error.
+pub fn template_variables(name: &str) -> Result<Vec<String>, String> {
+    let tera = Tera::new(&templates::get_template_path()).map_err(|err| err.to_string())?;
+    let mut walker = VariableWalker {
+        tera: &tera,
+        visited: HashSet::new(),
+        found: Vec::new(),
+    };
+    walker.template(name, &HashSet::new())?;
+    Ok(walker.found)
+}
+
+/// Assert that every variable `T`'s template reads exists on `T`.
+///
+/// Meant for tests, panics with the missing variable names.
+///
+/// # Example
+/// ```rust,no_run
+/// # use serde::Serialize;
+/// # use wenzetu::typed::WenzetuTemplate;
+/// # #[derive(Serialize, WenzetuTemplate)]
+/// # #[template("index.html")]
+/// # struct IndexPage { title: String }
+/// #[test]
+/// fn index_page_matches_template() {
+///     wenzetu::typed::assert_template_fields::<IndexPage>();
+/// }
+/// ```
+pub fn assert_template_fields<T: WenzetuTemplate>() {
+    let variables = template_variables(T::TEMPLATE)
+        .unwrap_or_else(|err| panic!("failed to inspect template '{}': {}", T::TEMPLATE, err));
+
+    let missing: Vec<_> = variables
+        .iter()
+        .filter(|var| !T::FIELDS.contains(&var.as_str()))
+        .collect();
+
+    assert!(
+        missing.is_empty(),
+        "template '{}' uses variables missing from {}: {:?}",
+        T::TEMPLATE,
+        std::any::type_name::<T>(),
+        missing
+    );
+}
+
+struct VariableWalker<'a> {
+    tera: &'a Tera,
+    /// Templates already walked, with the locals they were walked with
+    visited: HashSet<(String, Vec<String>)>,
+    found: Vec<String>,
+}
+
+impl VariableWalker<'_> {
+    /// Walk a template, `locals` being the variables the including template
+    /// defined
+    fn template(&mut self, name: &str, locals: &HashSet<String>) -> Result<(), String> {
+        let mut sorted: Vec<String> = locals.iter().cloned().collect();
+        sorted.sort();
+        if !self.visited.insert((name.to_string(), sorted)) {
+            return Ok(());
+        }
+
+        let tera = self.tera;
+        let template = tera.get_template(name).map_err(|err| err.to_string())?;
+
+        // Rendering starts from the root of the `extends` chain, with blocks
+        // resolved through the child template's definitions
+        let root = match template.parents.last() {
+            Some(parent) => tera.get_template(parent).map_err(|err| err.to_string())?,
+            None => template,
+        };
+
+        let mut locals = locals.clone();
+        self.nodes(&root.ast, template, &mut locals)
+    }
+
+    fn nodes(
+        &mut self,
+        nodes: &[Node],
+        template: &tera::Template,
+        locals: &mut HashSet<String>,
+    ) -> Result<(), String> {
+        for node in nodes {
+            match node {
+                Node::VariableBlock(_, expr) => self.expr(expr, locals),
+                Node::Set(_, set) => {
+                    self.expr(&set.value, locals);
+                    locals.insert(set.key.clone());
+                }
+                Node::FilterSection(_, section, _) => {
+                    self.call(&section.filter, locals);
+                    self.nodes(&section.body, template, locals)?;
+                }
+                Node::Block(_, block, _) => {
+                    let definitions = template.blocks_definitions.get(&block.name);
+                    match definitions {
+                        Some(definitions) => {
+                            // Definitions go from the most derived template up
+                            // to the root, keep walking while `super()` is called
+                            for (_, definition) in definitions {
+                                self.nodes(&definition.body, template, locals)?;
+                                if !definition.body.iter().any(|n| matches!(n, Node::Super)) {
+                                    break;
+                                }
+                            }
+                        }
+                        None => self.nodes(&block.body, template, locals)?,
+                    }
+                }
+                Node::Forloop(_, forloop, _) => {
+                    self.expr(&forloop.container, locals);
+
+                    let mut inner = locals.clone();
+                    inner.insert("loop".to_string());
+                    inner.insert(forloop.value.clone());
+                    if let Some(key) = &forloop.key {
+                        inner.insert(key.clone());
+                    }
+                    self.nodes(&forloop.body, template, &mut inner)?;
+
+                    if let Some(empty_body) = &forloop.empty_body {
+                        self.nodes(empty_body, template, locals)?;
+                    }
+                }
+                Node::If(cond, _) => {
+                    for (_, expr, body) in &cond.conditions {
+                        self.expr(expr, locals);
+
+                        // `{% if x is defined %}` makes `x` safe to use inside
+                        let mut inner = locals.clone();
+                        if let ExprVal::Test(test) = &expr.val
+                            && test.name == "defined"
+                            && !test.negated
+                            && !expr.negated
+                        {
+                            inner.insert(top_level(&test.ident));
+                        }
+                        self.nodes(body, template, &mut inner)?;
+                    }
+                    if let Some((_, body)) = &cond.otherwise {
+                        self.nodes(body, template, locals)?;
+                    }
+                }
+                Node::Include(_, names, ignore_missing) => {
+                    let existing = names
+                        .iter()
+                        .find(|name| self.tera.get_template(name).is_ok());
+                    match existing {
+                        // Included templates see the loop and `set` variables
+                        Some(name) => self.template(name, locals)?,
+                        None if *ignore_missing => {}
+                        None => return Err(format!("included template not found: {:?}", names)),
+                    }
+                }
+                // Macros only see their own arguments, not the context
+                Node::MacroDefinition(..)
+                | Node::ImportMacro(..)
+                | Node::Extends(..)
+                | Node::Super
+                | Node::Text(_)
+                | Node::Raw(..)
+                | Node::Break(_)
+                | Node::Continue(_)
+                | Node::Comment(..) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self, expr: &Expr, locals: &HashSet<String>) {
+        for filter in &expr.filters {
+            self.call(filter, locals);
+        }
+        if expr.has_default_filter() {
+            return;
+        }
+        self.value(&expr.val, locals);
+    }
+
+    fn value(&mut self, value: &ExprVal, locals: &HashSet<String>) {
+        match value {
+            ExprVal::Ident(ident) => self.ident(ident, locals),
+            ExprVal::Math(math) => {
+                self.expr(&math.lhs, locals);
+                self.expr(&math.rhs, locals);
+            }
+            ExprVal::Logic(logic) => {
+                self.expr(&logic.lhs, locals);
+                self.expr(&logic.rhs, locals);
+            }
+            ExprVal::In(within) => {
+                self.expr(&within.lhs, locals);
+                self.expr(&within.rhs, locals);
+            }
+            // `x is defined` style tests are how templates guard optional
+            // variables, so only their arguments count
+            ExprVal::Test(test) => {
+                for arg in &test.args {
+                    self.expr(arg, locals);
+                }
+            }
+            ExprVal::MacroCall(call) => {
+                for arg in call.args.values() {
+                    self.expr(arg, locals);
+                }
+            }
+            ExprVal::FunctionCall(call) => self.call(call, locals),
+            ExprVal::Array(items) => {
+                for item in items {
+                    self.expr(item, locals);
+                }
+            }
+            ExprVal::StringConcat(concat) => {
+                for value in &concat.values {
+                    self.value(value, locals);
+                }
+            }
+            ExprVal::String(_) | ExprVal::Int(_) | ExprVal::Float(_) | ExprVal::Bool(_) => {}
+        }
+    }
+
+    fn call(&mut self, call: &FunctionCall, locals: &HashSet<String>) {
+        for arg in call.args.values() {
+            self.expr(arg, locals);
+        }
+    }
+
+    fn ident(&mut self, ident: &str, locals: &HashSet<String>) {
+        let top = top_level(ident);
+        if top.is_empty() || top == "__tera_context" || locals.contains(&top) {
+            return;
+        }
+        if !self.found.contains(&top) {
+            self.found.push(top);
+        }
+    }
+}
+
+/// `user` for `user.name` or `user["name"]`
+fn top_level(ident: &str) -> String {
+    ident
+        .split(['.', '['])
+        .next()
+        .unwrap_or(ident)
+        .trim()
+        .to_string()
+}
@@ -0,0 +1,346 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use axum::Router;
+use axum::body::Body;
+use http::{Request, Response, StatusCode, header};
+use http_body_util::BodyExt;
+use tower::ServiceExt;
+use wenzetu::app::{StaticConfig, StaticOptions};
+use wenzetu::state::Shared;
+use wenzetu::uncovr::config::{AppConfig as UncovRConfig, Environment};
+use wenzetu::uncovr::prelude::{ApiRouter, get};
+use wenzetu::{App, AppConfig, Error};
+
+/// An app serving the fixtures under `tests/`, as in production
+fn app() -> App {
+    App::new()
+        .environment(Environment::Production)
+        .templates_path("tests/templates/**/*")
+        .static_files("/public", "tests/public")
+}
+
+async fn get_path(router: Router, path: &str) -> Response<Body> {
+    router
+        .oneshot(Request::get(path).body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+}
+
+async fn body_text(response: Response<Body>) -> String {
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn serves_static_files() {
+    let router = app().build().unwrap();
+
+    let response = get_path(router.clone(), "/public/hello.txt").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = response.headers()[header::ETAG].clone();
+    assert_eq!(response.headers()[header::CACHE_CONTROL], "no-cache");
+    assert_eq!(body_text(response).await, "hello from disk\n");
+
+    let revalidated = router
+        .oneshot(
+            Request::get("/public/hello.txt")
+                .header(header::IF_NONE_MATCH, etag)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(revalidated.status(), StatusCode::NOT_MODIFIED);
+}
+
+#[tokio::test]
+async fn renders_error_templates() {
+    let router = app().not_found_template("errors/404.html").build().unwrap();
+
+    let response = get_path(router.clone(), "/public/missing.txt").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let html = body_text(response).await;
+    assert!(html.contains("<h1>404 Not Found</h1>"), "{}", html);
+    // Tera escapes `/` in HTML templates
+    assert!(
+        html.contains("<p>&#x2F;public&#x2F;missing.txt</p>"),
+        "{}",
+        html
+    );
+
+    let response = get_path(router, "/nowhere").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(body_text(response).await.contains("<p>&#x2F;nowhere</p>"));
+}
+
+#[derive(Clone)]
+struct Greeting(&'static str);
+
+#[tokio::test]
+async fn handlers_extract_shared_state() {
+    let routes = ApiRouter::new()
+        .route(
+            "/greet",
+            get(|Shared(greeting): Shared<Greeting>| async move { greeting.0 }),
+        )
+        .route(
+            "/name",
+            get(|Shared(config): Shared<AppConfig>| async move { config.app.name }),
+        )
+        .route(
+            "/missing",
+            get(|Shared(number): Shared<u32>| async move { number.to_string() }),
+        );
+    let router = app()
+        .with_config(UncovRConfig::new("greeter", "1.0.0").bind("127.0.0.1:0"))
+        .state(Greeting("hello"))
+        .web(routes)
+        .build()
+        .unwrap();
+
+    let response = get_path(router.clone(), "/greet").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_text(response).await, "hello");
+
+    // The config passed to `with_config` is the one handlers see
+    let response = get_path(router.clone(), "/name").await;
+    assert_eq!(body_text(response).await, "greeter");
+
+    let response = get_path(router, "/missing").await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[tokio::test]
+async fn startup_hooks_run_in_order() {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let (first, second) = (calls.clone(), calls.clone());
+
+    let result = App::new()
+        .on_startup(move |_| async move {
+            first.lock().unwrap().push("first");
+            Ok::<_, io::Error>(())
+        })
+        .on_startup(move |_| async move {
+            second.lock().unwrap().push("second");
+            Err(io::Error::other("stop before binding"))
+        })
+        .serve()
+        .await;
+
+    assert!(matches!(result, Err(Error::Startup(_))));
+    assert_eq!(*calls.lock().unwrap(), ["first", "second"]);
+}
+
+#[tokio::test]
+async fn failed_bind_runs_shutdown_hooks() {
+    let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = taken.local_addr().unwrap().to_string();
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let (started, first, second) = (calls.clone(), calls.clone(), calls.clone());
+
+    let result = App::new()
+        .with_config(UncovRConfig::new("busy", "1.0.0").bind(address))
+        .live_reload(false)
+        .on_startup(move |_| async move {
+            started.lock().unwrap().push("startup");
+            Ok::<_, io::Error>(())
+        })
+        .on_shutdown(move || async move { first.lock().unwrap().push("first") })
+        .on_shutdown(move || async move { second.lock().unwrap().push("second") })
+        .serve()
+        .await;
+
+    assert!(matches!(result, Err(Error::Bind(_))));
+    assert_eq!(*calls.lock().unwrap(), ["startup", "second", "first"]);
+}
+
+#[tokio::test]
+async fn failing_startup_hook_aborts_startup() {
+    let ready = Arc::new(Mutex::new(false));
+    let after = Arc::new(Mutex::new(false));
+    let (ready_flag, after_flag) = (ready.clone(), after.clone());
+
+    let result = App::new()
+        .on_startup(|_| async { Err(io::Error::other("database unreachable")) })
+        .on_startup(move |_| async move {
+            *after_flag.lock().unwrap() = true;
+            Ok::<_, io::Error>(())
+        })
+        .on_ready(move |_| *ready_flag.lock().unwrap() = true)
+        .serve()
+        .await;
+
+    let err = result.unwrap_err();
+    assert!(matches!(err, Error::Startup(_)));
+    assert_eq!(err.exit_code(), 75);
+    assert!(err.report().contains("database unreachable"));
+    assert!(!*after.lock().unwrap());
+    assert!(!*ready.lock().unwrap());
+}
+
+#[test]
+fn mounts_at_the_same_path_conflict() {
+    let result = App::new()
+        .static_files("/assets", "./public")
+        .static_files("/assets/", "./dist")
+        .build();
+
+    let err = result.unwrap_err();
+    assert!(matches!(err, Error::Config { .. }));
+    assert!(err.to_string().contains("'/assets'"), "{}", err);
+}
+
+#[test]
+fn nested_mounts_are_allowed() {
+    let result = App::new()
+        .live_reload(false)
+        .static_files("/public", "./public")
+        .static_files("/public/img", "./images")
+        .build();
+
+    assert!(result.is_ok());
+}
+
+#[test]
+fn mount_paths_are_compared_normalized() {
+    let result = App::new()
+        .live_reload(false)
+        .static_files("docs", "./public")
+        .api_with_config("/api", ApiRouter::new(), Some("//docs/"), None::<String>)
+        .build();
+
+    let err = result.unwrap_err();
+    assert!(matches!(err, Error::Config { .. }));
+    assert!(err.to_string().contains("API docs"), "{}", err);
+}
+
+#[cfg(feature = "live-reload")]
+#[test]
+fn live_reload_path_is_only_reserved_with_live_reload() {
+    let mount = |live_reload| {
+        App::new()
+            .live_reload(live_reload)
+            .static_files("/_wenzetu/live-reload", "./public")
+            .build()
+    };
+
+    assert!(mount(false).is_ok());
+    let err = mount(true).unwrap_err();
+    assert!(err.to_string().contains("live reload events"), "{}", err);
+}
+
+#[cfg(feature = "live-reload")]
+#[tokio::test]
+async fn live_reload_defaults_to_development_only() {
+    let page = || {
+        ApiRouter::new().route(
+            "/",
+            get(|| async { axum::response::Html("<html><body>Hi</body></html>") }),
+        )
+    };
+    let html = |environment| async move {
+        let router = App::new()
+            .environment(environment)
+            .templates_path("tests/templates/**/*")
+            .web(page())
+            .build()
+            .unwrap();
+        body_text(get_path(router, "/").await).await
+    };
+
+    assert!(html(Environment::Development).await.contains("<script"));
+    assert!(!html(Environment::Production).await.contains("<script"));
+}
+
+#[tokio::test]
+async fn handler_errors_are_kept() {
+    let missing = || get(|| async { StatusCode::NOT_FOUND });
+    let router = app()
+        .not_found_template("errors/404.html")
+        .web(ApiRouter::new().route("/gone", missing()))
+        .api("/api", ApiRouter::new().route("/gone", missing()))
+        .build()
+        .unwrap();
+
+    for path in ["/gone", "/api/gone"] {
+        let response = get_path(router.clone(), path).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(body_text(response).await.is_empty(), "{}", path);
+    }
+
+    // Paths no route matches are still answered by Wenzetu
+    let response = get_path(router, "/api/nowhere").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn static_access_rules_deny_private_files() {
+    let outside = tempfile::tempdir().unwrap();
+    std::fs::write(outside.path().join("secret.txt"), "secret").unwrap();
+    let root = tempfile::tempdir().unwrap();
+    let public = root.path();
+    std::fs::write(public.join(".env"), "KEY=secret").unwrap();
+    std::fs::create_dir(public.join(".hidden")).unwrap();
+    std::fs::write(public.join(".hidden/x.txt"), "hidden").unwrap();
+    std::fs::write(public.join("dump.sql"), "DROP TABLE users;").unwrap();
+    std::fs::write(public.join("app.css"), "body {}").unwrap();
+    std::os::unix::fs::symlink(outside.path().join("secret.txt"), public.join("leak.txt")).unwrap();
+    std::os::unix::fs::symlink(public.join("app.css"), public.join("alias.css")).unwrap();
+
+    let router = |options: Option<StaticOptions>| {
+        let mut config = StaticConfig::new("/public", public.to_str().unwrap());
+        if let Some(options) = options {
+            config = config.options(options);
+        }
+        App::new()
+            .live_reload(false)
+            .static_config(config)
+            .build()
+            .unwrap()
+    };
+    let defaults = router(None);
+    let restricted = router(Some(
+        StaticOptions::default()
+            .follow_symlinks(false)
+            .allowed_extensions(["css", "txt"]),
+    ));
+
+    for router in [defaults.clone(), restricted.clone()] {
+        for path in ["/public/.env", "/public/.hidden/x.txt", "/public/leak.txt"] {
+            let status = get_path(router.clone(), path).await.status();
+            assert_eq!(status, StatusCode::NOT_FOUND, "{}", path);
+        }
+        let response = get_path(router, "/public/app.css").await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    // Symlinks inside the directory and the extension allowlist are only
+    // denied once set
+    let response = get_path(restricted.clone(), "/public/alias.css").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = get_path(defaults.clone(), "/public/alias.css").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = get_path(restricted, "/public/dump.sql").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = get_path(defaults, "/public/dump.sql").await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[test]
+fn unreadable_asset_manifests_fail_the_build() {
+    let result = App::new()
+        .live_reload(false)
+        .static_config(
+            StaticConfig::new("/public", "tests/public")
+                .fingerprint(true)
+                .manifest("tests/public/missing.json"),
+        )
+        .build();
+
+    let err = result.unwrap_err();
+    assert!(matches!(err, Error::Config { .. }));
+    assert!(err.to_string().contains("missing.json"), "{}", err);
+}
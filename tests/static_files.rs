@@ -0,0 +1,266 @@
+use std::fs::File;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use axum::Router;
+use axum::body::Body;
+use http::{HeaderMap, Request, Response, StatusCode, header};
+use http_body_util::BodyExt;
+use tempfile::TempDir;
+use tower::ServiceExt;
+use wenzetu::App;
+use wenzetu::app::StaticConfig;
+
+/// Router serving `config`, which points at a temporary directory
+fn router(config: StaticConfig) -> Router {
+    App::new()
+        .live_reload(false)
+        .static_config(config)
+        .build()
+        .unwrap()
+}
+
+async fn request(
+    router: &Router,
+    path: &str,
+    headers: &[(header::HeaderName, &str)],
+) -> Response<Body> {
+    let mut request = Request::get(path);
+    for (name, value) in headers {
+        request = request.header(name, *value);
+    }
+    router
+        .clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+}
+
+async fn body(response: Response<Body>) -> Vec<u8> {
+    response
+        .into_body()
+        .collect()
+        .await
+        .unwrap()
+        .to_bytes()
+        .to_vec()
+}
+
+/// Write `content` to `name` with a modification time `age` in the past
+fn write(dir: &Path, name: &str, content: &[u8], age: u64) {
+    let path = dir.join(name);
+    std::fs::write(&path, content).unwrap();
+    let time = SystemTime::now() - Duration::from_secs(age);
+    File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(time)
+        .unwrap();
+}
+
+fn encoding(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::CONTENT_ENCODING)
+        .map(|value| value.to_str().unwrap())
+}
+
+fn public() -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "app.js", b"console.log('plain');", 60);
+    write(dir.path(), "app.js.br", b"brotli bytes", 30);
+    write(dir.path(), "app.js.gz", b"gzip bytes", 30);
+    dir
+}
+
+#[tokio::test]
+async fn negotiates_precompressed_siblings() {
+    let dir = public();
+    let router = router(StaticConfig::new("/public", dir.path().to_str().unwrap()));
+
+    let mut etags = Vec::new();
+    for (accept, expected, content) in [
+        ("br, gzip", Some("br"), &b"brotli bytes"[..]),
+        ("gzip", Some("gzip"), b"gzip bytes"),
+        ("identity", None, b"console.log('plain');"),
+    ] {
+        let response = request(
+            &router,
+            "/public/app.js",
+            &[(header::ACCEPT_ENCODING, accept)],
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(encoding(response.headers()), expected, "{}", accept);
+        etags.push(response.headers()[header::ETAG].clone());
+        assert_eq!(body(response).await, content);
+    }
+
+    // Each encoding is its own representation
+    etags.dedup();
+    assert_eq!(etags.len(), 3);
+}
+
+#[tokio::test]
+async fn ignores_siblings_older_than_the_file() {
+    let dir = public();
+    // The file was edited after its siblings were made
+    write(dir.path(), "app.js", b"console.log('edited');", 0);
+    let router = router(StaticConfig::new("/public", dir.path().to_str().unwrap()));
+
+    let response = request(
+        &router,
+        "/public/app.js",
+        &[(header::ACCEPT_ENCODING, "br, gzip")],
+    )
+    .await;
+    assert_eq!(encoding(response.headers()), None);
+    assert_eq!(body(response).await, b"console.log('edited');");
+}
+
+#[tokio::test]
+async fn applies_cache_control_rules_by_path() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("fonts")).unwrap();
+    write(dir.path(), "fonts/body.woff2", b"font", 0);
+    write(dir.path(), "app.css", b"body {}", 0);
+    write(dir.path(), "index.html", b"<html></html>", 0);
+    let router = router(
+        StaticConfig::new("/public", dir.path().to_str().unwrap())
+            .cache_control("fonts/**", "public, max-age=31536000, immutable")
+            .cache_control("*.css", "public, max-age=3600")
+            .default_cache_control("no-store"),
+    );
+
+    for (path, expected) in [
+        (
+            "/public/fonts/body.woff2",
+            "public, max-age=31536000, immutable",
+        ),
+        ("/public/app.css", "public, max-age=3600"),
+        ("/public/index.html", "no-store"),
+    ] {
+        let response = request(&router, path, &[]).await;
+        assert_eq!(
+            response.headers()[header::CACHE_CONTROL],
+            expected,
+            "{}",
+            path
+        );
+    }
+}
+
+#[tokio::test]
+async fn revalidates_with_last_modified() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "app.css", b"body {}", 60);
+    let served = router(StaticConfig::new("/public", dir.path().to_str().unwrap()).etag(false));
+
+    let response = request(&served, "/public/app.css", &[]).await;
+    assert!(!response.headers().contains_key(header::ETAG));
+    let last_modified = response.headers()[header::LAST_MODIFIED]
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    let response = request(
+        &served,
+        "/public/app.css",
+        &[(header::IF_MODIFIED_SINCE, &last_modified)],
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    let response = request(
+        &served,
+        "/public/app.css",
+        &[(header::IF_MODIFIED_SINCE, "Thu, 01 Jan 1970 00:00:00 GMT")],
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let hidden =
+        router(StaticConfig::new("/public", dir.path().to_str().unwrap()).last_modified(false));
+    let response = request(
+        &hidden,
+        "/public/app.css",
+        &[(header::IF_MODIFIED_SINCE, &last_modified)],
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key(header::LAST_MODIFIED));
+}
+
+#[cfg(feature = "compression")]
+#[tokio::test]
+async fn compresses_on_the_fly() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = tempfile::tempdir().unwrap();
+    let large = "body { color: red; }\n".repeat(200);
+    write(dir.path(), "app.css", large.as_bytes(), 0);
+    write(dir.path(), "small.css", b"body {}", 0);
+    let router = router(
+        StaticConfig::new("/public", dir.path().to_str().unwrap())
+            .compress(true)
+            .compress_cache_dir(cache.path().to_str().unwrap()),
+    );
+
+    let response = request(
+        &router,
+        "/public/app.css",
+        &[(header::ACCEPT_ENCODING, "gzip")],
+    )
+    .await;
+    assert_eq!(encoding(response.headers()), Some("gzip"));
+    let gzip_etag = response.headers()[header::ETAG].clone();
+    let compressed = body(response).await;
+    assert_eq!(compressed[..2], [0x1f, 0x8b]);
+    assert!(compressed.len() < large.len());
+
+    let response = request(&router, "/public/app.css", &[]).await;
+    assert_eq!(encoding(response.headers()), None);
+    assert_ne!(response.headers()[header::ETAG], gzip_etag);
+    assert_eq!(body(response).await, large.as_bytes());
+
+    // Files too small to compress are served from the static directory,
+    // with the ETag of that file
+    let plain = request(&router, "/public/small.css", &[]).await;
+    let response = request(
+        &router,
+        "/public/small.css",
+        &[(header::ACCEPT_ENCODING, "gzip")],
+    )
+    .await;
+    assert_eq!(encoding(response.headers()), None);
+    assert_eq!(
+        response.headers()[header::ETAG],
+        plain.headers()[header::ETAG]
+    );
+}
+
+#[tokio::test]
+async fn spa_fallback_serves_the_app_shell() {
+    let dir = tempfile::tempdir().unwrap();
+    for sub in ["assets", "docs", "guide"] {
+        std::fs::create_dir(dir.path().join(sub)).unwrap();
+    }
+    write(dir.path(), "index.html", b"<div id=app></div>", 0);
+    write(dir.path(), "assets/app.js", b"mount();", 0);
+    write(dir.path(), "guide/index.html", b"<h1>Guide</h1>", 0);
+    let router = router(StaticConfig::new("/app", dir.path().to_str().unwrap()).spa(true));
+
+    for path in ["/app/users/42/settings", "/app/docs", "/app/docs/"] {
+        let response = request(&router, path, &[]).await;
+        assert_eq!(response.status(), StatusCode::OK, "{}", path);
+        assert_eq!(body(response).await, b"<div id=app></div>", "{}", path);
+    }
+
+    // Directories with their own index keep it
+    let response = request(&router, "/app/guide/", &[]).await;
+    assert_eq!(body(response).await, b"<h1>Guide</h1>");
+
+    // Missing assets are not client-side routes
+    let response = request(&router, "/app/assets/missing.js", &[]).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = request(&router, "/app/assets/app.js", &[]).await;
+    assert_eq!(body(response).await, b"mount();");
+}
@@ -0,0 +1,112 @@
+use serde::Serialize;
+use wenzetu::typed::{WenzetuTemplate, assert_template_fields, template_variables};
+
+#[derive(Serialize)]
+struct Author {
+    name: String,
+}
+
+#[derive(Serialize, WenzetuTemplate)]
+#[template("typed/list.html")]
+struct ListPage {
+    title: String,
+    author: Author,
+    #[serde(rename = "entries")]
+    items: Vec<Author>,
+    #[serde(rename(serialize = "type", deserialize = "kind"))]
+    kind: String,
+    #[serde(skip)]
+    cache_key: String,
+    #[serde(skip_serializing, default)]
+    draft: bool,
+}
+
+#[derive(Serialize, WenzetuTemplate)]
+#[template("typed/entry.html")]
+struct EntryPage {
+    r#type: String,
+    entry: Author,
+}
+
+#[derive(Serialize, WenzetuTemplate)]
+#[serde(rename_all = "camelCase")]
+#[template("typed/camel.html")]
+struct CamelPage {
+    page_title: String,
+    author_name: String,
+}
+
+fn load_templates() {
+    wenzetu::templates::init_templates("tests/templates/**/*");
+}
+
+#[test]
+fn fields_follow_serde_renames_and_skips() {
+    assert_eq!(ListPage::TEMPLATE, "typed/list.html");
+    assert_eq!(ListPage::FIELDS, ["title", "author", "entries", "type"]);
+}
+
+#[test]
+fn skipped_fields_stay_out_of_the_context() {
+    let page = ListPage {
+        title: "Authors".to_string(),
+        author: Author {
+            name: "Ada".to_string(),
+        },
+        items: Vec::new(),
+        kind: "list".to_string(),
+        cache_key: "authors:1".to_string(),
+        draft: true,
+    };
+
+    let context = page.context().unwrap().into_json();
+    assert_eq!(context["type"], "list");
+    assert!(context.get("cache_key").is_none() && !page.cache_key.is_empty());
+    assert!(context.get("draft").is_none() && page.draft);
+}
+
+#[test]
+fn raw_identifiers_lose_their_prefix() {
+    assert_eq!(EntryPage::FIELDS, ["type", "entry"]);
+}
+
+#[test]
+fn nested_fields_are_top_level_names() {
+    // `author.name` is provided by the `author` field
+    assert!(ListPage::FIELDS.contains(&"author"));
+    assert!(!ListPage::FIELDS.contains(&"name"));
+}
+
+#[test]
+fn included_templates_see_loop_variables() {
+    load_templates();
+
+    let variables = template_variables("typed/list.html").unwrap();
+    assert_eq!(variables, ["title", "author", "entries", "type"]);
+    assert_template_fields::<ListPage>();
+}
+
+#[test]
+#[should_panic(expected = "author")]
+fn missing_fields_are_reported() {
+    load_templates();
+
+    // Outside the loop, `author` is not defined by the including template
+    assert_template_fields::<EntryPage>();
+}
+
+#[test]
+fn fields_follow_the_container_rename_rule() {
+    assert_eq!(CamelPage::FIELDS, ["pageTitle", "authorName"]);
+
+    let page = CamelPage {
+        page_title: "Home".to_string(),
+        author_name: "Ada".to_string(),
+    };
+    let context = page.context().unwrap().into_json();
+    assert_eq!(context["pageTitle"], "Home");
+    assert_eq!(context["authorName"], "Ada");
+
+    load_templates();
+    assert_template_fields::<CamelPage>();
+}